@@ -0,0 +1,29 @@
+-- Emails become unique ignoring case. Report the users whose emails only differ by case,
+-- surrounding spaces or Unicode composition: they must be merged or renamed before migrating.
+DO $$
+DECLARE
+    collisions text;
+BEGIN
+    SELECT string_agg(format('%s users: %s', total, ids), E'\n')
+    INTO collisions
+    FROM (
+        SELECT count(*) AS total, string_agg(id::text, ', ' ORDER BY created_at) AS ids
+        FROM users
+        GROUP BY lower(normalize(trim(email), NFC))
+        HAVING count(*) > 1
+    ) AS duplicated;
+
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION E'Users with emails colliding case-insensitively:\n%', collisions;
+    END IF;
+END $$;
+
+-- Store existing emails in the normalized form used by the API: trimmed, NFC, lowercase domain.
+UPDATE users
+SET email = split_part(normalize(trim(email), NFC), '@', 1)
+    || '@' || lower(substring(normalize(trim(email), NFC) FROM '@([^@]*)$'))
+WHERE position('@' IN email) > 0
+  AND array_length(string_to_array(email, '@'), 1) = 2;
+
+DROP INDEX users_email;
+CREATE UNIQUE INDEX users_email_lower ON users (lower(email));
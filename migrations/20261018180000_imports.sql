@@ -0,0 +1,19 @@
+-- Background CSV imports. The uploaded file is kept with the job so any instance can resume it
+-- from processed_rows once the lease of the instance processing it expires.
+CREATE TABLE imports
+(
+    id uuid NOT NULL CONSTRAINT imports_pkey PRIMARY KEY,
+    status text NOT NULL,
+    data bytea NOT NULL,
+    total_rows bigint NOT NULL,
+    processed_rows bigint NOT NULL DEFAULT 0,
+    created_rows bigint NOT NULL DEFAULT 0,
+    failed_rows bigint NOT NULL DEFAULT 0,
+    errors jsonb NOT NULL DEFAULT '[]',
+    error text,
+    locked_until timestamp with time zone,
+    created_at timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at timestamp with time zone
+);
+
+CREATE INDEX imports_unfinished ON imports (created_at) WHERE status IN ('pending', 'running');
@@ -0,0 +1,12 @@
+-- custom_data becomes free-form JSON, validated by the API against the CUSTOM_DATA_SCHEMA file.
+-- Existing composite values keep their attributes: (7) becomes {"random": 7}.
+ALTER TABLE users
+    ALTER COLUMN custom_data TYPE jsonb
+        USING CASE
+            WHEN custom_data IS NULL THEN '{}'::jsonb
+            ELSE jsonb_strip_nulls(to_jsonb(custom_data))
+        END,
+    ALTER COLUMN custom_data SET DEFAULT '{}',
+    ALTER COLUMN custom_data SET NOT NULL;
+
+DROP TYPE custom_data;
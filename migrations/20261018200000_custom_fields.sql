@@ -0,0 +1,16 @@
+-- Custom fields defined at runtime through /v1/admin/custom_fields; custom_data is checked against
+-- them on every write.
+CREATE TABLE custom_fields
+(
+    name text NOT NULL CONSTRAINT custom_fields_pkey PRIMARY KEY,
+    type text NOT NULL,
+    required boolean NOT NULL DEFAULT false,
+    default_value jsonb,
+    enum_values jsonb,
+    indexed boolean NOT NULL DEFAULT false,
+    created_at timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at timestamp with time zone
+);
+
+-- Serves filters on indexed custom fields, which query custom_data @> '{"field": value}'.
+CREATE INDEX users_custom_data ON users USING gin (custom_data jsonb_path_ops);
@@ -0,0 +1,7 @@
+-- Search on users: full-text prefix matches on name and email plus pg_trgm word similarity for
+-- partial words and typos.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX users_search ON users USING gin (to_tsvector('simple', name || ' ' || email));
+CREATE INDEX users_name_trgm ON users USING gin (name gin_trgm_ops);
+CREATE INDEX users_email_trgm ON users USING gin (email gin_trgm_ops);
@@ -0,0 +1,4 @@
+-- jsonb_path_ops only serves containment. The default jsonb_ops also serves the ? of exists filters
+-- and narrows range filters down to the users having the key.
+DROP INDEX users_custom_data;
+CREATE INDEX users_custom_data ON users USING gin (custom_data);
@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::create_user::CreateUser;
+use crate::error::Error;
+use crate::user::User;
+
+const DEFAULT_MAX_OPERATIONS: usize = 1000;
+
+/// Limits of `POST /v1/user/_bulk`.
+#[derive(Debug, Clone, Copy)]
+pub struct BulkConfig {
+    pub max_operations: usize,
+}
+
+impl Default for BulkConfig {
+    fn default() -> Self {
+        Self { max_operations: DEFAULT_MAX_OPERATIONS }
+    }
+}
+
+impl BulkConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_operations: std::env::var("BULK_MAX_OPERATIONS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_MAX_OPERATIONS),
+        }
+    }
+}
+
+/// How a bulk request handles failed operations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkMode {
+    /// Any failure rolls back every operation.
+    #[default]
+    Atomic,
+    /// Failures are reported per operation and the others are kept.
+    BestEffort,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkRequest {
+    #[serde(default)]
+    pub mode: BulkMode,
+    pub operations: Vec<BulkOperation>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BulkOperation {
+    Create { user: CreateUser },
+    Update { user: User },
+    Delete { id: Uuid },
+}
+
+impl BulkOperation {
+    /// Checks the user of a create or update against the rules of single writes.
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            BulkOperation::Create { user } => user.validate(),
+            BulkOperation::Update { user } => user.validate(),
+            BulkOperation::Delete { .. } => Ok(()),
+        }
+    }
+}
+
+/// Outcome of a single bulk operation.
+#[derive(Debug, Serialize)]
+pub struct BulkItemResult {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
+impl BulkItemResult {
+    pub fn user(status: u16, user: User) -> Self {
+        Self { status, user: Some(user), id: None, error: None }
+    }
+
+    pub fn deleted(id: Uuid) -> Self {
+        Self { status: 204, user: None, id: Some(id), error: None }
+    }
+
+    pub fn failed(error: Error) -> Self {
+        Self { status: error.status, user: None, id: None, error: Some(error) }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkResponse {
+    pub results: Vec<BulkItemResult>,
+}
+
+/// Error of an atomic bulk request, pointing at the operation that failed.
+pub fn operation_error(index: usize, error: Error) -> Error {
+    let field = match &error.field {
+        Some(field) => format!("operations[{}].{}", index, field),
+        None => format!("operations[{}]", index),
+    };
+    Error::new(format!("Operation {} failed: {}", index, error.message), error.status).with_field(field)
+}
+
+/// Splits operations into the valid ones and the failures of the others, by index.
+pub fn split_invalid(operations: &[BulkOperation]) -> (Vec<BulkOperation>, Vec<(usize, Error)>) {
+    let mut valid = Vec::with_capacity(operations.len());
+    let mut invalid = Vec::new();
+    for (index, operation) in operations.iter().enumerate() {
+        match operation.validate() {
+            Ok(()) => valid.push(operation.clone()),
+            Err(err) => invalid.push((index, err)),
+        }
+    }
+    (valid, invalid)
+}
+
+/// Puts the failures of `split_invalid` back at their index among the results of the valid operations.
+pub fn merge_failures(results: Vec<BulkItemResult>, failures: Vec<(usize, Error)>) -> Vec<BulkItemResult> {
+    let mut merged = Vec::with_capacity(results.len() + failures.len());
+    let mut results = results.into_iter();
+    for (index, error) in failures {
+        while merged.len() < index {
+            match results.next() {
+                Some(result) => merged.push(result),
+                None => break,
+            }
+        }
+        merged.push(BulkItemResult::failed(error));
+    }
+    merged.extend(results);
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn request_defaults_to_atomic_mode() {
+        let id = Uuid::new_v4();
+        let request: BulkRequest = serde_json::from_value(json!({
+            "operations": [{"op": "delete", "id": id}]
+        }))
+        .unwrap();
+        assert_eq!(request.mode, BulkMode::Atomic);
+        assert!(matches!(request.operations[0], BulkOperation::Delete { id: deleted } if deleted == id));
+    }
+
+    #[test]
+    fn operation_error_points_at_the_operation() {
+        let error = Error::new("Invalid email".to_string(), 422).with_field("email".to_string());
+        let error = operation_error(3, error);
+        assert_eq!(error.status, 422);
+        assert_eq!(error.field.as_deref(), Some("operations[3].email"));
+        assert_eq!(error.message, "Operation 3 failed: Invalid email");
+    }
+
+    #[test]
+    fn invalid_operations_keep_their_position() {
+        let request: BulkRequest = serde_json::from_value(json!({
+            "operations": [
+                {"op": "delete", "id": Uuid::new_v4()},
+                {"op": "create", "user": {"email": "a@b.com", "name": " ", "birth_date": "1977-03-10"}},
+                {"op": "delete", "id": Uuid::new_v4()},
+                {"op": "create", "user": {"email": "not-an-email", "name": "Ana", "birth_date": "1977-03-10"}},
+            ]
+        }))
+        .unwrap();
+        let (valid, invalid) = split_invalid(&request.operations);
+        assert_eq!(valid.len(), 2);
+        assert_eq!(invalid.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(invalid[0].1.field.as_deref(), Some("name"));
+
+        let results = valid.iter().map(|_| BulkItemResult::deleted(Uuid::new_v4())).collect();
+        let statuses: Vec<u16> = merge_failures(results, invalid).iter().map(|result| result.status).collect();
+        assert_eq!(statuses, vec![204, 422, 204, 422]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::time::SystemTime;
+
+use actix_web::http::header::{
+    EntityTag, Header, HeaderValue, HttpDate, IfModifiedSince, IfNoneMatch, LastModified, CACHE_CONTROL, ETAG,
+    IF_NONE_MATCH,
+};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use chrono::{DateTime, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+
+const DEFAULT_CACHE_CONTROL: &str = "no-cache";
+
+/// `Cache-Control` of the cacheable routes, e.g. `private, max-age=60`. The default `no-cache`
+/// lets caches keep responses as long as they revalidate them with the validators below.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// `GET /v1/user/{id}`.
+    pub user: String,
+    /// `GET /v1/user`.
+    pub users: String,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { user: DEFAULT_CACHE_CONTROL.to_string(), users: DEFAULT_CACHE_CONTROL.to_string() }
+    }
+}
+
+impl CacheConfig {
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).unwrap_or_else(|_| DEFAULT_CACHE_CONTROL.to_string());
+        Self { user: var("CACHE_CONTROL_USER"), users: var("CACHE_CONTROL_USERS") }
+    }
+
+    /// The configuration registered in the app, or the default one.
+    pub fn of(req: &HttpRequest) -> Self {
+        req.app_data::<actix_web::web::Data<CacheConfig>>()
+            .map(|config| config.get_ref().clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Validators of a response body, compared with the conditional headers of the request.
+pub struct Validators {
+    pub etag: EntityTag,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl Validators {
+    /// A strong `ETag`: a digest of the exact body, which changes with any byte of it.
+    pub fn strong(body: &[u8], last_modified: Option<DateTime<Utc>>) -> Self {
+        Self { etag: EntityTag::new_strong(digest(body)), last_modified }
+    }
+
+    /// A weak `ETag`, for bodies like collections that are only semantically equivalent.
+    pub fn weak(body: &[u8]) -> Self {
+        Self { etag: EntityTag::new_weak(digest(body)), last_modified: None }
+    }
+
+    /// Whether the client copy is current: `If-None-Match` matches with the weak comparison or,
+    /// without it, nothing changed since `If-Modified-Since`.
+    fn not_modified(&self, req: &HttpRequest) -> bool {
+        if req.headers().contains_key(IF_NONE_MATCH) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(etags)) => etags.iter().any(|etag| etag.weak_eq(&self.etag)),
+                Err(_) => false,
+            };
+        }
+        match (IfModifiedSince::parse(req), self.last_modified) {
+            // HTTP dates have no fractions of a second.
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => {
+                SystemTime::from(Utc.timestamp(last_modified.timestamp(), 0)) <= SystemTime::from(since)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Sends `body` with its validators and `Cache-Control`, or a bodyless 304 when the client copy
+/// is current.
+pub fn conditional(
+    req: &HttpRequest,
+    mut res: HttpResponseBuilder,
+    body: Vec<u8>,
+    validators: Validators,
+    cache_control: &str,
+) -> HttpResponse {
+    let mut not_modified = HttpResponse::NotModified();
+    for builder in [&mut res, &mut not_modified] {
+        builder.insert_header((ETAG, validators.etag.clone()));
+        if let Some(last_modified) = validators.last_modified {
+            builder.insert_header(LastModified(HttpDate::from(SystemTime::from(last_modified))));
+        }
+        match HeaderValue::from_str(cache_control) {
+            Ok(value) => {
+                builder.insert_header((CACHE_CONTROL, value));
+            }
+            Err(e) => tracing::error!("Invalid Cache-Control {}: {}", cache_control, e),
+        }
+    }
+    if validators.not_modified(req) {
+        return not_modified.finish();
+    }
+    res.body(body)
+}
+
+fn digest(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    format!("{:x}", digest)[..32].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::IF_MODIFIED_SINCE;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    fn respond(req: &HttpRequest, validators: Validators) -> HttpResponse {
+        conditional(req, HttpResponse::Ok(), b"{}".to_vec(), validators, "private, max-age=60")
+    }
+
+    #[test]
+    fn matching_etag_is_not_modified() {
+        let validators = Validators::strong(b"{}", None);
+        let etag = validators.etag.to_string();
+        let res = respond(&TestRequest::default().to_http_request(), Validators::strong(b"{}", None));
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(ETAG).unwrap().to_str().unwrap(), etag);
+        assert_eq!(res.headers().get(CACHE_CONTROL).unwrap(), "private, max-age=60");
+
+        let if_none_match = format!("\"other\", W/{}", etag);
+        let req = TestRequest::default().insert_header((IF_NONE_MATCH, if_none_match)).to_http_request();
+        let res = respond(&req, validators);
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(res.headers().get(ETAG).is_some());
+
+        let req = TestRequest::default().insert_header((IF_NONE_MATCH, "\"other\"")).to_http_request();
+        assert_eq!(respond(&req, Validators::weak(b"{}")).status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn if_modified_since_compares_whole_seconds() {
+        let updated_at = Utc.ymd(2026, 10, 18).and_hms_milli(10, 0, 0, 500);
+        let since = |date: &str| TestRequest::default().insert_header((IF_MODIFIED_SINCE, date)).to_http_request();
+
+        let res = respond(&since("Sun, 18 Oct 2026 10:00:00 GMT"), Validators::strong(b"{}", Some(updated_at)));
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get("last-modified").unwrap(), "Sun, 18 Oct 2026 10:00:00 GMT");
+        let res = respond(&since("Sun, 18 Oct 2026 09:59:59 GMT"), Validators::strong(b"{}", Some(updated_at)));
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // If-None-Match wins over If-Modified-Since.
+        let req = TestRequest::default()
+            .insert_header((IF_NONE_MATCH, "\"other\""))
+            .insert_header((IF_MODIFIED_SINCE, "Sun, 18 Oct 2026 10:00:00 GMT"))
+            .to_http_request();
+        assert_eq!(respond(&req, Validators::strong(b"{}", Some(updated_at))).status(), StatusCode::OK);
+    }
+}
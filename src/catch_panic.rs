@@ -0,0 +1,155 @@
+use std::any::Any;
+use std::future::{ready, Ready};
+use std::panic::AssertUnwindSafe;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::{HttpMessage, HttpResponse};
+use futures::future::{FutureExt, LocalBoxFuture};
+
+use crate::error::Error;
+use crate::request_id::RequestId;
+
+/// Middleware turning a panic while serving a request into a structured 500 response.
+///
+/// Must be wrapped inside [`crate::request_id::RequestIdMiddleware`] so the panic is logged and
+/// answered with the request id.
+pub struct CatchPanic;
+
+impl<S, B> Transform<S, ServiceRequest> for CatchPanic
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = CatchPanicMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CatchPanicMiddleware { service }))
+    }
+}
+
+pub struct CatchPanicMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for CatchPanicMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req.extensions().get::<RequestId>().cloned();
+
+        let fut = match std::panic::catch_unwind(AssertUnwindSafe(|| self.service.call(req))) {
+            Ok(fut) => fut,
+            Err(panic) => return Box::pin(ready(Err(panic_error(panic, request_id)))),
+        };
+
+        Box::pin(async move {
+            match AssertUnwindSafe(fut).catch_unwind().await {
+                Ok(result) => result,
+                Err(panic) => Err(panic_error(panic, request_id)),
+            }
+        })
+    }
+}
+
+fn panic_error(panic: Box<dyn Any + Send>, request_id: Option<RequestId>) -> actix_web::Error {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    let request_id = request_id.map(|id| id.to_string());
+    tracing::error!(
+        request_id = request_id.as_deref().unwrap_or_default(),
+        "Panic while handling request: {}",
+        message
+    );
+
+    let mut error = Error::new("Internal server error".to_string(), 500);
+    if let Some(request_id) = request_id {
+        error = error.with_request_id(request_id);
+    }
+    let res = HttpResponse::InternalServerError().json(&error);
+    InternalError::from_response(message, res).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MockRepository;
+    use crate::request_id::{RequestIdMiddleware, REQUEST_ID_HEADER};
+    use actix_web::http::StatusCode;
+    use actix_web::{web, App};
+
+    const REQUEST_ID: &str = "71802ecd-4eb3-4381-af7e-f737e3a35d5d";
+
+    #[actix_rt::test]
+    async fn panicking_repository_returns_structured_500() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_all().returning(|| panic!("connection lost"));
+
+        let app = App::new()
+            .wrap(CatchPanic)
+            .wrap(RequestIdMiddleware)
+            .app_data(web::Data::new(repo))
+            .configure(crate::v1::service::<MockRepository>);
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/v1/user")
+            .insert_header((REQUEST_ID_HEADER, REQUEST_ID))
+            .to_request();
+        let res = app.call(req)
+            .await
+            .unwrap_err()
+            .error_response();
+
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            res.headers().get(REQUEST_ID_HEADER).and_then(|h| h.to_str().ok()),
+            Some(REQUEST_ID)
+        );
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        let error: Error = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.status, 500);
+        assert_eq!(error.request_id.as_deref(), Some(REQUEST_ID));
+    }
+
+    #[actix_rt::test]
+    async fn worker_keeps_serving_after_a_panic() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_all().times(1).returning(|| panic!("connection lost"));
+        repo.expect_get_user()
+            .returning(|id| Err(crate::Error::new(format!("{} not found", id), 404)));
+
+        let app = App::new()
+            .wrap(CatchPanic)
+            .wrap(RequestIdMiddleware)
+            .app_data(web::Data::new(repo))
+            .configure(crate::v1::service::<MockRepository>);
+        let app = actix_web::test::init_service(app).await;
+
+        let req = actix_web::test::TestRequest::get().uri("/v1/user").to_request();
+        let err = app.call(req).await.unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let req = actix_web::test::TestRequest::get()
+            .uri(&format!("/v1/user/{}", REQUEST_ID))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,19 +1,39 @@
 use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+use crate::custom_data::{self, CustomData};
+use crate::error::Error;
+use crate::redact::{Redacted, RedactedEmail};
+use crate::user::validate_fields;
+
+#[derive(Clone, Serialize, Deserialize, FromRow)]
 pub struct CreateUser {
     pub email: String,
     pub name: String,
     pub birth_date: NaiveDate,
+    #[serde(default = "custom_data::empty")]
     pub custom_data: CustomData,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
-#[sqlx(type_name = "custom_data")]
-pub struct CustomData {
-    pub random: i32,
+impl CreateUser {
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_fields(&self.email, &self.name, self.birth_date)
+    }
+}
+
+impl fmt::Debug for CreateUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreateUser")
+            .field("email", &RedactedEmail(&self.email))
+            .field("name", &Redacted(&self.name))
+            .field("birth_date", &Redacted(&self.birth_date))
+            .field("custom_data", &Redacted(&self.custom_data))
+            .field("created_at", &self.created_at)
+            .field("updated_at", &self.updated_at)
+            .finish()
+    }
 }
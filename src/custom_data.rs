@@ -0,0 +1,118 @@
+use jsonschema::paths::PathChunk;
+use jsonschema::JSONSchema;
+use serde_json::{Map, Value};
+
+use crate::error::Error;
+
+/// Free-form attributes of a user, stored as JSONB.
+pub type CustomData = Value;
+
+/// `custom_data` of users written without one.
+pub fn empty() -> CustomData {
+    Value::Object(Map::new())
+}
+
+/// JSON Schema every `custom_data` must satisfy; without one any JSON object is accepted.
+#[derive(Default)]
+pub struct CustomDataSchema {
+    schema: Option<JSONSchema>,
+}
+
+impl CustomDataSchema {
+    /// Reads the schema from the file named by `CUSTOM_DATA_SCHEMA`, when set.
+    pub fn from_env() -> Result<Self, Error> {
+        let path = match std::env::var("CUSTOM_DATA_SCHEMA") {
+            Ok(path) => path,
+            Err(_) => return Ok(Self::default()),
+        };
+        let file = std::fs::read_to_string(&path)
+            .map_err(|e| Error::new(format!("Error on read custom data schema {}: {}", path, e), 500))?;
+        let schema = serde_json::from_str(&file)
+            .map_err(|e| Error::new(format!("Invalid custom data schema {}: {}", path, e), 500))?;
+        Self::new(&schema)
+    }
+
+    pub fn new(schema: &Value) -> Result<Self, Error> {
+        let schema = JSONSchema::compile(schema)
+            .map_err(|e| Error::new(format!("Invalid custom data schema: {}", e), 500))?;
+        Ok(Self { schema: Some(schema) })
+    }
+
+    /// Checks `custom_data` is an object satisfying the schema, reporting the first violation
+    /// with the path of the offending value, e.g. `custom_data.tags[0]`.
+    pub fn validate(&self, custom_data: &CustomData) -> Result<(), Error> {
+        if !custom_data.is_object() {
+            return Err(Error::new("Custom data must be a JSON object".to_string(), 422)
+                .with_field("custom_data".to_string()));
+        }
+        let schema = match &self.schema {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+
+        match schema.validate(custom_data) {
+            Ok(()) => Ok(()),
+            Err(mut errors) => {
+                let error = match errors.next() {
+                    Some(error) => error,
+                    None => return Ok(()),
+                };
+                let field = error.instance_path.iter().fold("custom_data".to_string(), |field, chunk| match chunk {
+                    PathChunk::Property(property) => format!("{}.{}", field, property),
+                    PathChunk::Index(index) => format!("{}[{}]", field, index),
+                    PathChunk::Keyword(_) => field,
+                });
+                Err(Error::new(format!("Invalid custom data: {}", error), 422).with_field(field))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> CustomDataSchema {
+        CustomDataSchema::new(&json!({
+            "type": "object",
+            "properties": {
+                "random": {"type": "integer"},
+                "tags": {"type": "array", "items": {"type": "string"}}
+            },
+            "required": ["random"]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn validate_accepts_matching_data() {
+        assert!(schema().validate(&json!({"random": 1, "tags": ["a"], "extra": true})).is_ok());
+    }
+
+    #[test]
+    fn validate_points_at_the_invalid_value() {
+        let err = schema().validate(&json!({"random": "um"})).unwrap_err();
+        assert_eq!(err.status, 422);
+        assert_eq!(err.field.as_deref(), Some("custom_data.random"));
+
+        let err = schema().validate(&json!({"random": 1, "tags": ["a", 2]})).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("custom_data.tags[1]"));
+
+        let err = schema().validate(&json!({})).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("custom_data"));
+    }
+
+    #[test]
+    fn validate_requires_an_object() {
+        let err = CustomDataSchema::default().validate(&json!([1])).unwrap_err();
+        assert_eq!(err.status, 422);
+        assert_eq!(err.field.as_deref(), Some("custom_data"));
+        assert!(CustomDataSchema::default().validate(&json!({"any": [1]})).is_ok());
+    }
+
+    #[test]
+    fn new_rejects_invalid_schemas() {
+        assert!(CustomDataSchema::new(&json!({"type": 1})).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use crate::error::Error;
+
+/// Prefix of the query parameters filtering on `custom_data`, e.g. `custom_data.score[gte]=10`.
+pub const FILTER_PREFIX: &str = "custom_data.";
+/// Most values an `in` filter may list.
+pub const MAX_IN_VALUES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterOperator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// Any of comma separated values, see [`CustomDataFilter::values`].
+    In,
+    /// Whether the attribute is set, with `true` or `false`.
+    Exists,
+}
+
+impl FilterOperator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterOperator::Eq => "eq",
+            FilterOperator::Ne => "ne",
+            FilterOperator::Gt => "gt",
+            FilterOperator::Gte => "gte",
+            FilterOperator::Lt => "lt",
+            FilterOperator::Lte => "lte",
+            FilterOperator::In => "in",
+            FilterOperator::Exists => "exists",
+        }
+    }
+
+    pub fn parse(operator: &str) -> Option<Self> {
+        [
+            FilterOperator::Eq,
+            FilterOperator::Ne,
+            FilterOperator::Gt,
+            FilterOperator::Gte,
+            FilterOperator::Lt,
+            FilterOperator::Lte,
+            FilterOperator::In,
+            FilterOperator::Exists,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == operator)
+    }
+
+    /// Whether the operator orders values, which booleans do not support.
+    pub fn is_range(&self) -> bool {
+        matches!(self, FilterOperator::Gt | FilterOperator::Gte | FilterOperator::Lt | FilterOperator::Lte)
+    }
+}
+
+/// A condition on a `custom_data` attribute, with its value as sent in the query string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomDataFilter {
+    /// Path of the attribute below `custom_data`, e.g. `score`.
+    pub path: String,
+    pub operator: FilterOperator,
+    pub value: String,
+}
+
+impl CustomDataFilter {
+    /// Reads a query parameter, `None` when it does not filter on `custom_data`.
+    pub fn parse(key: &str, value: &str) -> Result<Option<Self>, Error> {
+        let key = match key.strip_prefix(FILTER_PREFIX) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let (path, operator) = match key.strip_suffix(']').and_then(|key| key.split_once('[')) {
+            Some((path, operator)) => {
+                let operator = FilterOperator::parse(operator).ok_or_else(|| {
+                    Error::new(format!("Unknown filter operator {}", operator), 400)
+                        .with_field(format!("{}{}", FILTER_PREFIX, key))
+                })?;
+                (path, operator)
+            }
+            None => (key, FilterOperator::Eq),
+        };
+        if path.is_empty() {
+            return Err(Error::new("Filter path must not be empty".to_string(), 400)
+                .with_field(format!("{}{}", FILTER_PREFIX, key)));
+        }
+        Ok(Some(Self { path: path.to_string(), operator, value: value.to_string() }))
+    }
+
+    /// Path of the attribute in a user, used as the field of its errors.
+    pub fn field(&self) -> String {
+        format!("{}{}", FILTER_PREFIX, self.path)
+    }
+
+    /// Values listed by an `in` filter: at most [`MAX_IN_VALUES`], separated by commas, each in
+    /// double quotes when it contains one, e.g. `"Rio, RJ",Recife`. Quoted values escape `"` and
+    /// `\` with a backslash.
+    pub fn values(&self) -> Result<Vec<String>, Error> {
+        let invalid = |message: String| Error::new(message, 400).with_field(self.field());
+        let mut values = Vec::new();
+        let mut rest = self.value.as_str();
+        loop {
+            let (value, after) = match rest.strip_prefix('"') {
+                Some(quoted) => {
+                    let (value, after) = unquote(quoted)
+                        .ok_or_else(|| invalid(format!("{}[in] has an unterminated quoted value", self.field())))?;
+                    if !after.is_empty() && !after.starts_with(',') {
+                        return Err(invalid(format!("{}[in] values must be separated by commas", self.field())));
+                    }
+                    (value, after)
+                }
+                None => {
+                    let end = rest.find(',').unwrap_or(rest.len());
+                    (rest[..end].to_string(), &rest[end..])
+                }
+            };
+            values.push(value);
+            if values.len() > MAX_IN_VALUES {
+                let message = format!("{}[in] accepts at most {} values", self.field(), MAX_IN_VALUES);
+                return Err(invalid(message));
+            }
+            match after.strip_prefix(',') {
+                Some(next) => rest = next,
+                None => return Ok(values),
+            }
+        }
+    }
+}
+
+/// Reads a quoted value up to its closing quote, returning it and the text after that quote.
+fn unquote(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 1..])),
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// The `custom_data` filters of a query string.
+pub fn from_query(pairs: &[(String, String)]) -> Result<Vec<CustomDataFilter>, Error> {
+    pairs
+        .iter()
+        .filter_map(|(key, value)| CustomDataFilter::parse(key, value).transpose())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_path_and_operator() {
+        let filter = CustomDataFilter::parse("custom_data.score[gte]", "10").unwrap().unwrap();
+        assert_eq!(filter.path, "score");
+        assert_eq!(filter.operator, FilterOperator::Gte);
+        assert_eq!(filter.value, "10");
+
+        let filter = CustomDataFilter::parse("custom_data.plan", "pro").unwrap().unwrap();
+        assert_eq!(filter.operator, FilterOperator::Eq);
+        assert_eq!(filter.field(), "custom_data.plan");
+    }
+
+    #[test]
+    fn parse_ignores_other_parameters() {
+        assert_eq!(CustomDataFilter::parse("email", "a@b.com").unwrap(), None);
+    }
+
+    #[test]
+    fn values_reads_quoted_and_plain_values() {
+        let values = |value: &str| CustomDataFilter::parse("custom_data.city[in]", value).unwrap().unwrap().values();
+        assert_eq!(values("Recife,Natal").unwrap(), vec!["Recife", "Natal"]);
+        assert_eq!(values(r#""Rio, RJ",Recife"#).unwrap(), vec!["Rio, RJ", "Recife"]);
+        assert_eq!(values(r#"Recife,"o \"Rio\" \\ RJ""#).unwrap(), vec!["Recife", r#"o "Rio" \ RJ"#]);
+        assert_eq!(values("").unwrap(), vec![""]);
+
+        let err = values(r#""Rio, RJ"#).unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (400, Some("custom_data.city")));
+        assert!(values(r#""Rio"RJ"#).is_err());
+    }
+
+    #[test]
+    fn values_are_limited() {
+        let filter = |count: usize| CustomDataFilter {
+            path: "score".to_string(),
+            operator: FilterOperator::In,
+            value: vec!["1"; count].join(","),
+        };
+        assert_eq!(filter(MAX_IN_VALUES).values().unwrap().len(), MAX_IN_VALUES);
+        let err = filter(MAX_IN_VALUES + 1).values().unwrap_err();
+        assert_eq!(err.message, "custom_data.score[in] accepts at most 100 values");
+    }
+
+    #[test]
+    fn parse_rejects_unknown_operators() {
+        let err = CustomDataFilter::parse("custom_data.score[like]", "1").unwrap_err();
+        assert_eq!(err.status, 400);
+        assert_eq!(err.field.as_deref(), Some("custom_data.score[like]"));
+        assert!(CustomDataFilter::parse("custom_data.[gt]", "1").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+use crate::custom_data::CustomData;
+use crate::error::Error;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CustomFieldType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    /// An ISO 8601 date string, e.g. `1977-03-10`.
+    Date,
+}
+
+impl CustomFieldType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CustomFieldType::String => "string",
+            CustomFieldType::Integer => "integer",
+            CustomFieldType::Number => "number",
+            CustomFieldType::Boolean => "boolean",
+            CustomFieldType::Date => "date",
+        }
+    }
+
+    pub fn parse(field_type: &str) -> Option<Self> {
+        [
+            CustomFieldType::String,
+            CustomFieldType::Integer,
+            CustomFieldType::Number,
+            CustomFieldType::Boolean,
+            CustomFieldType::Date,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == field_type)
+    }
+
+    pub fn accepts(&self, value: &Value) -> bool {
+        match self {
+            CustomFieldType::String => value.is_string(),
+            CustomFieldType::Integer => value.is_i64() || value.is_u64(),
+            CustomFieldType::Number => value.is_number(),
+            CustomFieldType::Boolean => value.is_boolean(),
+            CustomFieldType::Date => value.as_str().is_some_and(|date| date.parse::<NaiveDate>().is_ok()),
+        }
+    }
+
+    /// Reads a value of this type from text, e.g. a query string parameter.
+    pub fn value_from_str(&self, text: &str) -> Option<Value> {
+        match self {
+            CustomFieldType::String => Some(Value::String(text.to_string())),
+            CustomFieldType::Integer => text.parse::<i64>().ok().map(Value::from),
+            CustomFieldType::Number => text.parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number),
+            CustomFieldType::Boolean => text.parse::<bool>().ok().map(Value::Bool),
+            CustomFieldType::Date => text.parse::<NaiveDate>().ok().map(|_| Value::String(text.to_string())),
+        }
+    }
+}
+
+/// Definition of a `custom_data` attribute, managed at runtime through the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomField {
+    /// Key of the attribute in `custom_data`; taken from the path on updates.
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: CustomFieldType,
+    #[serde(default)]
+    pub required: bool,
+    /// Value set on users written without the attribute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    /// Only values the attribute may take, when restricted.
+    #[serde(default, rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub enum_values: Option<Vec<Value>>,
+    /// Whether users can be filtered by the attribute. No index is created for it: every attribute
+    /// is served by the single `users_custom_data` index, so the flag only gates filtering.
+    #[serde(default)]
+    pub indexed: bool,
+    #[serde(default, skip_deserializing)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_deserializing)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl CustomField {
+    /// Checks the definition is consistent: a valid name and enum and default values of its type.
+    pub fn validate(&self) -> Result<(), Error> {
+        let valid_name = self.name.len() <= MAX_NAME_LENGTH
+            && self.name.starts_with(|c: char| c.is_ascii_lowercase())
+            && self.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid_name {
+            let message = format!(
+                "Name must have at most {} lowercase letters, digits or underscores, starting with a letter",
+                MAX_NAME_LENGTH
+            );
+            return Err(Error::new(message, 422).with_field("name".to_string()));
+        }
+        if let Some(values) = &self.enum_values {
+            if values.is_empty() {
+                return Err(Error::new("Enum must not be empty".to_string(), 422).with_field("enum".to_string()));
+            }
+            if let Some(index) = values.iter().position(|value| !self.field_type.accepts(value)) {
+                let message = format!("Enum values must be of type {}", self.field_type.as_str());
+                return Err(Error::new(message, 422).with_field(format!("enum[{}]", index)));
+            }
+        }
+        if let Some(default) = &self.default {
+            self.check(default).map_err(|err| err.with_field("default".to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Checks a value of the attribute against the type and enum of the definition.
+    pub fn check(&self, value: &Value) -> Result<(), Error> {
+        if !self.field_type.accepts(value) {
+            let message = format!("Custom field {} must be of type {}", self.name, self.field_type.as_str());
+            return Err(Error::new(message, 422).with_field(self.path()));
+        }
+        if let Some(values) = &self.enum_values {
+            if !values.contains(value) {
+                let allowed: Vec<String> = values.iter().map(Value::to_string).collect();
+                let message = format!("Custom field {} must be one of {}", self.name, allowed.join(", "));
+                return Err(Error::new(message, 422).with_field(self.path()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The property, `type` or `enum`, by which values valid under `previous` may be invalid
+    /// under this definition; `None` when every one of them stays valid.
+    pub fn restricted_from(&self, previous: &CustomField) -> Option<&'static str> {
+        let widened = previous.field_type == CustomFieldType::Integer && self.field_type == CustomFieldType::Number;
+        if self.field_type != previous.field_type && !widened {
+            return Some("type");
+        }
+        match (&self.enum_values, &previous.enum_values) {
+            (None, _) => None,
+            (Some(values), Some(previous)) if previous.iter().all(|value| values.contains(value)) => None,
+            (Some(_), _) => Some("enum"),
+        }
+    }
+
+    /// Whether users without the attribute, valid under `previous`, could no longer be written
+    /// as no default fills it in.
+    pub fn requires_missing(&self, previous: &CustomField) -> bool {
+        let requires = |field: &CustomField| field.required && field.default.is_none();
+        requires(self) && !requires(previous)
+    }
+
+    /// Path of the attribute in a user, used as the field of its errors.
+    pub fn path(&self) -> String {
+        format!("custom_data.{}", self.name)
+    }
+}
+
+/// Checks `custom_data` against the definitions, returning it with the defaults of missing
+/// attributes filled in. Attributes without a definition are kept as they are.
+pub fn apply(fields: &[CustomField], custom_data: &CustomData) -> Result<CustomData, Error> {
+    let mut object = match custom_data.as_object() {
+        Some(object) => object.clone(),
+        None => return Ok(custom_data.clone()),
+    };
+    for field in fields {
+        match object.get(&field.name).filter(|value| !value.is_null()) {
+            Some(value) => field.check(value)?,
+            None => match &field.default {
+                Some(default) => {
+                    object.insert(field.name.clone(), default.clone());
+                }
+                None if field.required => {
+                    let message = format!("Custom field {} is required", field.name);
+                    return Err(Error::new(message, 422).with_field(field.path()));
+                }
+                None => {}
+            },
+        }
+    }
+    Ok(Value::Object(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field(value: Value) -> CustomField {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn fields() -> Vec<CustomField> {
+        vec![
+            field(json!({"name": "plan", "type": "string", "enum": ["free", "pro"], "default": "free"})),
+            field(json!({"name": "score", "type": "integer", "required": true})),
+            field(json!({"name": "since", "type": "date"})),
+        ]
+    }
+
+    #[test]
+    fn apply_fills_defaults_and_keeps_other_attributes() {
+        let custom_data = apply(&fields(), &json!({"score": 3, "random": 1})).unwrap();
+        assert_eq!(custom_data, json!({"plan": "free", "score": 3, "random": 1}));
+    }
+
+    #[test]
+    fn apply_rejects_missing_required_fields() {
+        let err = apply(&fields(), &json!({"plan": "pro"})).unwrap_err();
+        assert_eq!(err.status, 422);
+        assert_eq!(err.field.as_deref(), Some("custom_data.score"));
+    }
+
+    #[test]
+    fn apply_checks_types_and_enums() {
+        let err = apply(&fields(), &json!({"score": 1.5})).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("custom_data.score"));
+
+        let err = apply(&fields(), &json!({"score": 1, "plan": "gold"})).unwrap_err();
+        assert_eq!(err.message, "Custom field plan must be one of \"free\", \"pro\"");
+
+        let err = apply(&fields(), &json!({"score": 1, "since": "10/03/1977"})).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("custom_data.since"));
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_definitions() {
+        let err = field(json!({"name": "Plan", "type": "string"})).validate().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("name"));
+
+        let err = field(json!({"name": "plan", "type": "string", "enum": ["free", 1]})).validate().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("enum[1]"));
+
+        let err = field(json!({"name": "plan", "type": "string", "enum": ["free"], "default": "pro"}))
+            .validate()
+            .unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("default"));
+
+        assert!(fields().iter().all(|field| field.validate().is_ok()));
+    }
+
+    #[test]
+    fn restricted_from_finds_changes_invalidating_stored_values() {
+        let plan = field(json!({"name": "plan", "type": "string", "enum": ["free", "pro"]}));
+        let changed = |changes: Value| {
+            let mut definition = serde_json::to_value(&plan).unwrap();
+            crate::patch::merge_patch(&mut definition, &changes);
+            field(definition)
+        };
+        assert_eq!(changed(json!({"indexed": true})).restricted_from(&plan), None);
+        assert_eq!(changed(json!({"enum": ["free", "pro", "team"]})).restricted_from(&plan), None);
+        assert_eq!(changed(json!({"enum": null})).restricted_from(&plan), None);
+        assert_eq!(changed(json!({"enum": ["pro"]})).restricted_from(&plan), Some("enum"));
+        assert_eq!(changed(json!({"type": "integer", "enum": null})).restricted_from(&plan), Some("type"));
+
+        let score = field(json!({"name": "score", "type": "integer"}));
+        assert_eq!(field(json!({"name": "score", "type": "number"})).restricted_from(&score), None);
+        assert_eq!(score.restricted_from(&field(json!({"name": "score", "type": "number"}))), Some("type"));
+        let restricted = field(json!({"name": "score", "type": "integer", "enum": [1]}));
+        assert_eq!(restricted.restricted_from(&score), Some("enum"));
+    }
+
+    #[test]
+    fn requires_missing_ignores_fields_with_defaults() {
+        let optional = field(json!({"name": "score", "type": "integer"}));
+        let required = field(json!({"name": "score", "type": "integer", "required": true}));
+        let defaulted = field(json!({"name": "score", "type": "integer", "required": true, "default": 0}));
+        assert!(required.requires_missing(&optional));
+        assert!(required.requires_missing(&defaulted));
+        assert!(!defaulted.requires_missing(&optional));
+        assert!(!required.requires_missing(&required));
+        assert!(!optional.requires_missing(&required));
+    }
+
+    #[test]
+    fn value_from_str_reads_the_field_type() {
+        assert_eq!(CustomFieldType::Integer.value_from_str("10"), Some(json!(10)));
+        assert_eq!(CustomFieldType::Integer.value_from_str("dez"), None);
+        assert_eq!(CustomFieldType::Boolean.value_from_str("true"), Some(json!(true)));
+        assert_eq!(CustomFieldType::Date.value_from_str("1977-03-10"), Some(json!("1977-03-10")));
+    }
+}
@@ -0,0 +1,95 @@
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::Error;
+
+const GMAIL_DOMAINS: &[&str] = &["gmail.com", "googlemail.com"];
+
+/// How emails are normalized before being stored or looked up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmailRules {
+    /// Apply provider specific equivalences, e.g. Gmail ignoring dots and `+tags`.
+    pub provider_rules: bool,
+}
+
+impl EmailRules {
+    pub fn from_env() -> Self {
+        Self {
+            provider_rules: std::env::var("EMAIL_PROVIDER_RULES")
+                .map(|value| value.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+        }
+    }
+
+    /// Domains whose stored emails the provider rules may rewrite.
+    pub fn provider_domains(&self) -> &'static [&'static str] {
+        if self.provider_rules {
+            GMAIL_DOMAINS
+        } else {
+            &[]
+        }
+    }
+
+    /// Normalizes an email: trimmed, Unicode NFC, lowercase domain and, when enabled, provider
+    /// rules. The local part keeps its case; uniqueness is case-insensitive in the database.
+    pub fn normalize(&self, email: &str) -> Result<String, Error> {
+        let email: String = email.trim().nfc().collect();
+        let (local, domain) = match email.rsplit_once('@') {
+            Some((local, domain)) if !local.is_empty() && !domain.is_empty() => (local, domain),
+            _ => return Err(invalid_email()),
+        };
+        if domain.contains(char::is_whitespace) || local.contains(char::is_whitespace) {
+            return Err(invalid_email());
+        }
+
+        let domain = domain.to_lowercase();
+        if self.provider_rules && GMAIL_DOMAINS.contains(&domain.as_str()) {
+            let local = local.split('+').next().unwrap_or_default().replace('.', "");
+            if local.is_empty() {
+                return Err(invalid_email());
+            }
+            return Ok(format!("{}@gmail.com", local.to_lowercase()));
+        }
+        Ok(format!("{}@{}", local, domain))
+    }
+}
+
+fn invalid_email() -> Error {
+    Error::new("Invalid email".to_string(), 422).with_field("email".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROVIDER_RULES: EmailRules = EmailRules { provider_rules: true };
+
+    #[test]
+    fn normalize_trims_and_lowercases_domain() {
+        let email = EmailRules::default().normalize("  Foo.Bar@Example.COM ").unwrap();
+        assert_eq!(email, "Foo.Bar@example.com");
+    }
+
+    #[test]
+    fn normalize_composes_unicode() {
+        let decomposed = "jose\u{0301}@Exemplo.com";
+        let email = EmailRules::default().normalize(decomposed).unwrap();
+        assert_eq!(email, "jos\u{00e9}@exemplo.com");
+    }
+
+    #[test]
+    fn normalize_rejects_invalid_emails() {
+        for email in ["", "teste", "@teste.com", "teste@", "te ste@teste.com"] {
+            let err = EmailRules::default().normalize(email).unwrap_err();
+            assert_eq!(err.status, 422);
+            assert_eq!(err.field.as_deref(), Some("email"));
+        }
+    }
+
+    #[test]
+    fn gmail_rules_only_apply_when_enabled() {
+        let email = "Foo.Bar+news@GoogleMail.com";
+        assert_eq!(EmailRules::default().normalize(email).unwrap(), "Foo.Bar+news@googlemail.com");
+        assert_eq!(PROVIDER_RULES.normalize(email).unwrap(), "foobar@gmail.com");
+        assert_eq!(PROVIDER_RULES.normalize("Foo.Bar+news@teste.com").unwrap(), "Foo.Bar+news@teste.com");
+    }
+}
@@ -0,0 +1,411 @@
+use chrono::NaiveDate;
+
+use crate::custom_data_filter::FILTER_PREFIX;
+use crate::error::Error;
+
+/// Query parameter holding the expression, also the field of its errors.
+pub const FILTER_PARAMETER: &str = "filter";
+const MAX_LENGTH: usize = 2048;
+/// Deepest nesting of parentheses and `not`, so parsing cannot exhaust the stack.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    /// Case-insensitive substring match, on text only.
+    Contains,
+}
+
+impl Comparison {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "=",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Lte => "<=",
+            Comparison::Gt => ">",
+            Comparison::Gte => ">=",
+            Comparison::Contains => "~",
+        }
+    }
+
+    pub fn is_range(&self) -> bool {
+        matches!(self, Comparison::Lt | Comparison::Lte | Comparison::Gt | Comparison::Gte)
+    }
+}
+
+/// A field an expression may compare; anything else is rejected while parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterField {
+    Name,
+    Email,
+    BirthDate,
+    CreatedAt,
+    UpdatedAt,
+    /// Attribute below `custom_data`, checked against the custom field definitions when compiled.
+    CustomData(String),
+}
+
+impl FilterField {
+    pub fn parse(field: &str) -> Option<Self> {
+        match field {
+            "name" => Some(FilterField::Name),
+            "email" => Some(FilterField::Email),
+            "birth_date" => Some(FilterField::BirthDate),
+            "created_at" => Some(FilterField::CreatedAt),
+            "updated_at" => Some(FilterField::UpdatedAt),
+            _ => field
+                .strip_prefix(FILTER_PREFIX)
+                .filter(|path| !path.is_empty())
+                .map(|path| FilterField::CustomData(path.to_string())),
+        }
+    }
+
+    pub fn is_date(&self) -> bool {
+        matches!(self, FilterField::BirthDate | FilterField::CreatedAt | FilterField::UpdatedAt)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterValue {
+    String(String),
+    Number(f64),
+    /// An unquoted ISO 8601 date, e.g. `1990-01-01`.
+    Date(NaiveDate),
+    Boolean(bool),
+}
+
+impl FilterValue {
+    /// The value as it would be written in a query string parameter.
+    pub fn to_text(&self) -> String {
+        match self {
+            FilterValue::String(text) => text.clone(),
+            FilterValue::Number(number) => number.to_string(),
+            FilterValue::Date(date) => date.to_string(),
+            FilterValue::Boolean(boolean) => boolean.to_string(),
+        }
+    }
+}
+
+/// Parsed `filter` parameter of `GET /v1/user`, e.g.
+/// `name ~ "ana" and (birth_date < 1990-01-01 or custom_data.random > 5)`.
+///
+/// `and` binds tighter than `or`; `not` negates the condition after it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpression {
+    Compare { field: FilterField, comparison: Comparison, value: FilterValue },
+    Not(Box<FilterExpression>),
+    And(Box<FilterExpression>, Box<FilterExpression>),
+    Or(Box<FilterExpression>, Box<FilterExpression>),
+}
+
+impl FilterExpression {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        if text.len() > MAX_LENGTH {
+            return Err(invalid(format!("Filter must have at most {} characters", MAX_LENGTH)));
+        }
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0, end: text.len(), depth: 0 };
+        let expression = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            Some(token) => Err(parser.unexpected(token.position, "and, or or end of filter")),
+            None => Ok(expression),
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(message, 400).with_field(FILTER_PARAMETER.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    OpenParen,
+    CloseParen,
+    Word(String),
+    Comparison(Comparison),
+    Value(FilterValue),
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: TokenKind,
+    /// Offset of the token in the expression, reported in errors.
+    position: usize,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(position, c)) = chars.peek() {
+        let kind = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => {
+                chars.next();
+                TokenKind::OpenParen
+            }
+            ')' => {
+                chars.next();
+                TokenKind::CloseParen
+            }
+            '=' | '~' => {
+                chars.next();
+                TokenKind::Comparison(if c == '=' { Comparison::Eq } else { Comparison::Contains })
+            }
+            '!' | '<' | '>' => {
+                chars.next();
+                let equal = chars.next_if(|&(_, next)| next == '=').is_some();
+                TokenKind::Comparison(match (c, equal) {
+                    ('!', true) => Comparison::Ne,
+                    ('<', false) => Comparison::Lt,
+                    ('<', true) => Comparison::Lte,
+                    ('>', false) => Comparison::Gt,
+                    ('>', true) => Comparison::Gte,
+                    _ => return Err(invalid(format!("Expected != at position {}", position))),
+                })
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped @ ('"' | '\\'))) => string.push(escaped),
+                            _ => return Err(invalid(format!("Invalid escape in string at position {}", position))),
+                        },
+                        Some((_, c)) => string.push(c),
+                        None => return Err(invalid(format!("Unterminated string at position {}", position))),
+                    }
+                }
+                TokenKind::Value(FilterValue::String(string))
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let literal = take_while(&mut chars, |c| c.is_ascii_digit() || c == '-' || c == '.');
+                if let Ok(date) = NaiveDate::parse_from_str(&literal, "%Y-%m-%d") {
+                    TokenKind::Value(FilterValue::Date(date))
+                } else {
+                    let number = literal
+                        .parse::<f64>()
+                        .map_err(|_| invalid(format!("Invalid number or date {} at position {}", literal, position)))?;
+                    TokenKind::Value(FilterValue::Number(number))
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                TokenKind::Word(take_while(&mut chars, |c| c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            }
+            c => return Err(invalid(format!("Unexpected character {} at position {}", c, position))),
+        };
+        tokens.push(Token { kind, position });
+    }
+    Ok(tokens)
+}
+
+fn take_while(chars: &mut std::iter::Peekable<std::str::CharIndices>, accept: impl Fn(char) -> bool) -> String {
+    let mut taken = String::new();
+    while let Some((_, c)) = chars.next_if(|&(_, c)| accept(c)) {
+        taken.push(c);
+    }
+    taken
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Length of the expression, the position reported when it ends too early.
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.position).map(|token| &token.kind)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(TokenKind::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(TokenKind, usize), Error> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok((token.kind.clone(), token.position))
+            }
+            None => Err(self.unexpected(self.end, expected)),
+        }
+    }
+
+    fn unexpected(&self, position: usize, expected: &str) -> Error {
+        invalid(format!("Expected {} at position {}", expected, position))
+    }
+
+    fn or(&mut self) -> Result<FilterExpression, Error> {
+        let mut expression = self.and()?;
+        while self.keyword("or") {
+            expression = FilterExpression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<FilterExpression, Error> {
+        let mut expression = self.not()?;
+        while self.keyword("and") {
+            expression = FilterExpression::And(Box::new(expression), Box::new(self.not()?));
+        }
+        Ok(expression)
+    }
+
+    fn not(&mut self) -> Result<FilterExpression, Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(invalid(format!("Filter must nest at most {} levels", MAX_DEPTH)));
+        }
+        let expression = if self.keyword("not") {
+            FilterExpression::Not(Box::new(self.not()?))
+        } else if self.peek() == Some(&TokenKind::OpenParen) {
+            self.position += 1;
+            let expression = self.or()?;
+            match self.next(")")? {
+                (TokenKind::CloseParen, _) => expression,
+                (_, position) => return Err(self.unexpected(position, ")")),
+            }
+        } else {
+            self.comparison()?
+        };
+        self.depth -= 1;
+        Ok(expression)
+    }
+
+    fn comparison(&mut self) -> Result<FilterExpression, Error> {
+        let (field, position) = match self.next("field")? {
+            (TokenKind::Word(word), position) => {
+                let field = FilterField::parse(&word)
+                    .ok_or_else(|| invalid(format!("Unknown filter field {} at position {}", word, position)))?;
+                (field, position)
+            }
+            (_, position) => return Err(self.unexpected(position, "field")),
+        };
+        let comparison = match self.next("comparison")? {
+            (TokenKind::Comparison(comparison), _) => comparison,
+            (_, position) => return Err(self.unexpected(position, "comparison")),
+        };
+        let value = match self.next("value")? {
+            (TokenKind::Value(value), _) => value,
+            (TokenKind::Word(word), _) if word == "true" || word == "false" => FilterValue::Boolean(word == "true"),
+            (_, position) => return Err(self.unexpected(position, "value")),
+        };
+        check(&field, comparison, &value)
+            .map_err(|message| invalid(format!("{} at position {}", message, position)))?;
+        Ok(FilterExpression::Compare { field, comparison, value })
+    }
+}
+
+/// Checks the comparison and value suit the field, as far as known without custom field definitions.
+fn check(field: &FilterField, comparison: Comparison, value: &FilterValue) -> Result<(), String> {
+    match (field, value) {
+        (FilterField::Name | FilterField::Email, FilterValue::String(_)) if !comparison.is_range() => Ok(()),
+        (FilterField::Name | FilterField::Email, FilterValue::String(_)) => {
+            Err(format!("Operator {} is not supported by text fields", comparison.as_str()))
+        }
+        (FilterField::Name | FilterField::Email, _) => Err("Text fields must be compared with strings".to_string()),
+        (field, FilterValue::Date(_)) if field.is_date() && comparison != Comparison::Contains => Ok(()),
+        (field, _) if field.is_date() && comparison == Comparison::Contains => {
+            Err("Operator ~ is not supported by dates".to_string())
+        }
+        (field, _) if field.is_date() => Err("Dates must be compared with dates like 1990-01-01".to_string()),
+        (_, FilterValue::String(_)) => Ok(()),
+        (_, _) if comparison == Comparison::Contains => Err("Operator ~ needs a string".to_string()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(field: FilterField, comparison: Comparison, value: FilterValue) -> FilterExpression {
+        FilterExpression::Compare { field, comparison, value }
+    }
+
+    #[test]
+    fn parse_builds_the_tree_with_and_before_or() {
+        let expression =
+            FilterExpression::parse(r#"name ~ "ana" and (birth_date < 1990-01-01 or custom_data.random > 5)"#).unwrap();
+        let name = compare(FilterField::Name, Comparison::Contains, FilterValue::String("ana".to_string()));
+        let birth_date = compare(
+            FilterField::BirthDate,
+            Comparison::Lt,
+            FilterValue::Date(NaiveDate::from_ymd(1990, 1, 1)),
+        );
+        let random = compare(FilterField::CustomData("random".to_string()), Comparison::Gt, FilterValue::Number(5.0));
+        assert_eq!(
+            expression,
+            FilterExpression::And(
+                Box::new(name),
+                Box::new(FilterExpression::Or(Box::new(birth_date), Box::new(random)))
+            )
+        );
+
+        let expression = FilterExpression::parse(r#"email = "a" OR email = "b" AND NOT name = "c""#).unwrap();
+        assert!(matches!(expression, FilterExpression::Or(_, right) if matches!(*right, FilterExpression::And(..))));
+    }
+
+    #[test]
+    fn parse_reads_literals() {
+        let expression = FilterExpression::parse(r#"custom_data.tag = "say \"hi\"""#).unwrap();
+        let said = FilterValue::String("say \"hi\"".to_string());
+        assert!(matches!(expression, FilterExpression::Compare { value, .. } if value == said));
+        let expression = FilterExpression::parse("custom_data.active != true").unwrap();
+        assert!(matches!(expression, FilterExpression::Compare { value: FilterValue::Boolean(true), .. }));
+        let expression = FilterExpression::parse("custom_data.score >= -1.5").unwrap();
+        assert!(matches!(expression, FilterExpression::Compare { value: FilterValue::Number(n), .. } if n == -1.5));
+    }
+
+    #[test]
+    fn parse_rejects_fields_outside_the_allowlist() {
+        let err = FilterExpression::parse(r#"password = "x""#).unwrap_err();
+        assert_eq!(err.status, 400);
+        assert_eq!(err.field.as_deref(), Some("filter"));
+        assert_eq!(err.message, "Unknown filter field password at position 0");
+    }
+
+    #[test]
+    fn parse_rejects_mismatched_values_and_syntax_errors() {
+        for filter in [
+            "name = 5",
+            r#"name < "b""#,
+            r#"birth_date = "1990-01-01""#,
+            "birth_date ~ 1990-01-01",
+            "custom_data.score ~ 5",
+            r#"name = "ana"#,
+            r#"(name = "ana""#,
+            r#"name = "ana" name = "bia""#,
+            r#"name = "ana" and"#,
+            r#"name == "ana""#,
+            "name; drop table users",
+        ] {
+            assert!(FilterExpression::parse(filter).is_err(), "{} should be rejected", filter);
+        }
+    }
+
+    #[test]
+    fn parse_limits_nesting() {
+        let filter = format!("{}name = \"a\"{}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert!(FilterExpression::parse(&filter).is_err());
+        let filter = format!("{}name = \"a\"", "not ".repeat(MAX_DEPTH + 1));
+        assert!(FilterExpression::parse(&filter).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use std::time::Duration;
+
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::create_user::CreateUser;
+use crate::error::Error;
+use crate::repository::{Repository, RepositoryResult};
+use crate::user_csv;
+
+/// Failed rows kept on a job as samples; the others are only counted.
+pub const MAX_ERROR_SAMPLES: usize = 20;
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl ImportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportStatus::Pending => "pending",
+            ImportStatus::Running => "running",
+            ImportStatus::Completed => "completed",
+            ImportStatus::Failed => "failed",
+            ImportStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        [
+            ImportStatus::Pending,
+            ImportStatus::Running,
+            ImportStatus::Completed,
+            ImportStatus::Failed,
+            ImportStatus::Cancelled,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == status)
+    }
+}
+
+/// A failed row kept as a sample on its job.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportError {
+    pub line: u64,
+    pub status: u16,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl ImportError {
+    pub fn new(line: u64, error: Error) -> Self {
+        Self { line, status: error.status, message: error.message, field: error.field }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportJob {
+    pub id: Uuid,
+    pub status: ImportStatus,
+    pub total_rows: i64,
+    pub processed_rows: i64,
+    pub created_rows: i64,
+    pub failed_rows: i64,
+    pub errors: Vec<ImportError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Rows of a job processed together; stored atomically with the job progress.
+#[derive(Debug)]
+pub struct ImportBatch {
+    /// Rows of the file processed before this batch. A job found elsewhere was resumed by another
+    /// worker once this one's lease expired, and the batch is dropped.
+    pub from_row: i64,
+    /// Rows of the file processed once this batch is stored.
+    pub processed_rows: i64,
+    pub users: Vec<(u64, CreateUser)>,
+    pub failures: Vec<ImportError>,
+    /// How long the job stays locked to this worker after the batch.
+    pub lease: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImportConfig {
+    pub batch_size: usize,
+    pub poll_interval: Duration,
+    pub lease: Duration,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            poll_interval: Duration::from_secs(5),
+            lease: Duration::from_secs(300),
+        }
+    }
+}
+
+impl ImportConfig {
+    pub fn from_env() -> Self {
+        Self {
+            batch_size: std::env::var("IMPORT_BATCH_SIZE")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|size| *size > 0)
+                .unwrap_or(DEFAULT_BATCH_SIZE),
+            ..Self::default()
+        }
+    }
+}
+
+/// Processes import jobs in the background, resuming those left unfinished by a restart.
+pub fn spawn_worker<R: Repository>(repo: web::Data<R>, config: ImportConfig) {
+    actix_web::rt::spawn(async move {
+        loop {
+            match run_next(repo.get_ref(), &config).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => tracing::error!("Error on process import: {}", err.message),
+            }
+            actix_web::rt::time::sleep(config.poll_interval).await;
+        }
+    });
+}
+
+/// Claims the next unfinished job and processes it; `false` when there is none.
+pub async fn run_next<R: Repository>(repo: &R, config: &ImportConfig) -> RepositoryResult<bool> {
+    let (job, data) = match repo.claim_import(config.lease).await? {
+        Some(claimed) => claimed,
+        None => return Ok(false),
+    };
+    tracing::info!("Processing import {} from row {}", job.id, job.processed_rows);
+
+    let rows = match user_csv::rows(&data) {
+        Ok(rows) => rows,
+        Err(err) => {
+            repo.finish_import(&job.id, ImportStatus::Failed, Some(err.message)).await?;
+            return Ok(true);
+        }
+    };
+    let mut rows = rows.skip(job.processed_rows as usize);
+    let mut processed_rows = job.processed_rows;
+    loop {
+        let mut batch = ImportBatch {
+            from_row: processed_rows,
+            processed_rows,
+            users: Vec::new(),
+            failures: Vec::new(),
+            lease: config.lease,
+        };
+        for (line, user) in rows.by_ref().take(config.batch_size) {
+            batch.processed_rows += 1;
+            match user {
+                Ok(user) => batch.users.push((line, user)),
+                Err(err) => batch.failures.push(ImportError::new(line, err)),
+            }
+        }
+        if batch.processed_rows == processed_rows {
+            break;
+        }
+
+        processed_rows = batch.processed_rows;
+        let progress = repo.import_batch(&job.id, &batch).await?;
+        if progress.status == ImportStatus::Cancelled {
+            tracing::info!("Import {} was cancelled at row {}", job.id, progress.processed_rows);
+            return Ok(true);
+        }
+        if progress.processed_rows != processed_rows {
+            tracing::info!("Import {} was resumed by another worker at row {}", job.id, progress.processed_rows);
+            return Ok(true);
+        }
+    }
+
+    let job = repo.finish_import(&job.id, ImportStatus::Completed, None).await?;
+    tracing::info!(
+        "Import {} finished: {} created, {} failed",
+        job.id,
+        job.created_rows,
+        job.failed_rows
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MockRepository;
+    use mockall::predicate::eq;
+
+    const CSV: &str = "email,name,birth_date,custom_data.random\n\
+                       um@teste.com,Um,1977-03-10,1\n\
+                       invalid,Dois,1977-03-10,1\n\
+                       tres@teste.com,Tres,1977-03-10,1\n";
+
+    fn job(processed_rows: i64) -> ImportJob {
+        ImportJob {
+            id: Uuid::new_v4(),
+            status: ImportStatus::Running,
+            total_rows: 3,
+            processed_rows,
+            created_rows: 0,
+            failed_rows: 0,
+            errors: Vec::new(),
+            error: None,
+            created_at: Utc::now(),
+            updated_at: None,
+        }
+    }
+
+    fn config() -> ImportConfig {
+        ImportConfig { batch_size: 2, ..ImportConfig::default() }
+    }
+
+    #[actix_rt::test]
+    async fn run_next_processes_the_file_in_batches() {
+        let mut repo = MockRepository::default();
+        repo.expect_claim_import()
+            .returning(|_lease| Ok(Some((job(0), CSV.as_bytes().to_vec()))));
+        let mut sequence = mockall::Sequence::new();
+        repo.expect_import_batch()
+            .withf(|_id, batch| {
+                batch.processed_rows == 2 && batch.users.len() == 1 && batch.failures[0].line == 3
+            })
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_id, _batch| Ok(job(2)));
+        repo.expect_import_batch()
+            .withf(|_id, batch| batch.from_row == 2 && batch.processed_rows == 3 && batch.users[0].0 == 4)
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_id, _batch| Ok(job(3)));
+        repo.expect_finish_import()
+            .with(mockall::predicate::always(), eq(ImportStatus::Completed), eq(None))
+            .times(1)
+            .returning(|_id, _status, _error| Ok(job(3)));
+
+        assert!(run_next(&repo, &config()).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn run_next_resumes_after_processed_rows() {
+        let mut repo = MockRepository::default();
+        repo.expect_claim_import()
+            .returning(|_lease| Ok(Some((job(2), CSV.as_bytes().to_vec()))));
+        repo.expect_import_batch()
+            .withf(|_id, batch| batch.processed_rows == 3 && batch.users.len() == 1)
+            .times(1)
+            .returning(|_id, _batch| Ok(job(3)));
+        repo.expect_finish_import().times(1).returning(|_id, _status, _error| Ok(job(3)));
+
+        assert!(run_next(&repo, &config()).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn run_next_stops_when_cancelled() {
+        let mut repo = MockRepository::default();
+        repo.expect_claim_import()
+            .returning(|_lease| Ok(Some((job(0), CSV.as_bytes().to_vec()))));
+        repo.expect_import_batch().times(1).returning(|_id, _batch| {
+            Ok(ImportJob { status: ImportStatus::Cancelled, ..job(2) })
+        });
+        repo.expect_finish_import().never();
+
+        assert!(run_next(&repo, &config()).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn run_next_stops_when_resumed_by_another_worker() {
+        let mut repo = MockRepository::default();
+        repo.expect_claim_import()
+            .returning(|_lease| Ok(Some((job(0), CSV.as_bytes().to_vec()))));
+        repo.expect_import_batch()
+            .withf(|_id, batch| batch.from_row == 0)
+            .times(1)
+            .returning(|_id, _batch| Ok(job(3)));
+        repo.expect_finish_import().never();
+
+        assert!(run_next(&repo, &config()).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn run_next_without_jobs() {
+        let mut repo = MockRepository::default();
+        repo.expect_claim_import().returning(|_lease| Ok(None));
+
+        assert!(!run_next(&repo, &config()).await.unwrap());
+    }
+}
@@ -1,39 +1,70 @@
+// Tests kept from the original project predate these lints.
+#![cfg_attr(
+    test,
+    allow(clippy::zero_prefixed_literal, clippy::map_flatten, clippy::unnecessary_mut_passed, clippy::get_first)
+)]
+
+mod bulk;
+mod cache;
+mod catch_panic;
 mod create_user;
+mod custom_data;
+mod custom_data_filter;
+mod custom_field;
+mod email;
 mod error;
+mod filter_expression;
 mod health;
+mod import_job;
+#[cfg(test)]
+mod memory_repository;
+mod metrics;
+mod page;
+mod patch;
+mod patch_user;
+mod redact;
 mod repository;
+mod request_id;
+mod search;
+mod telemetry;
 mod user;
+mod user_csv;
 mod v1;
 
+use crate::bulk::BulkConfig;
+use crate::cache::CacheConfig;
+use crate::catch_panic::CatchPanic;
 use crate::error::Error;
+use crate::import_job::ImportConfig;
+use crate::metrics::{HttpMetrics, Metrics};
 use crate::repository::{PostgresRepository};
+use crate::request_id::RequestIdMiddleware;
+use crate::telemetry::{ServerSpan, TelemetryConfig};
 //use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::Arc;
-use tracing_subscriber::{EnvFilter};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
     // init tracing subscriber
-    let tracing = tracing_subscriber::fmt()
-        .with_timer(tracing_subscriber::fmt::time::UtcTime::rfc_3339())
-        .with_env_filter(EnvFilter::from_default_env());
-
-    if cfg!(debug_assertions) {
-        tracing.pretty().init();
-    } else {
-        tracing.json().init();
-    }
+    let tracer_provider = telemetry::init(&TelemetryConfig::from_env());
 
     let port = std::env::var("PORT").unwrap_or("8090".to_string());
     let address = format!("127.0.0.1:{}", port);
 
     tracing::info!("Starting server at {}", address);
     let thread_counter = Arc::new(AtomicU16::new(1));
-    let pos_repo = PostgresRepository::from_env().await.expect("Repository initialize error");
+    let metrics = Metrics::new();
+    let pos_repo = PostgresRepository::from_env()
+        .await
+        .expect("Repository initialize error")
+        .with_metrics(&metrics);
     let repo = web::Data::new(pos_repo);
+    let bulk_config = web::Data::new(BulkConfig::from_env());
+    let cache_config = web::Data::new(CacheConfig::from_env());
+    import_job::spawn_worker(repo.clone(), ImportConfig::from_env());
 
     HttpServer::new(move || {
         let thread_index = thread_counter.fetch_add(1, Ordering::SeqCst);
@@ -41,10 +72,18 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             //.wrap(Cors::default().supports_credentials())
+            .wrap(CatchPanic)
+            .wrap(HttpMetrics::new(metrics.clone()))
+            .wrap(RequestIdMiddleware)
+            .wrap(ServerSpan)
             .app_data(web::Data::new(thread_index))
+            .app_data(web::Data::new(metrics.clone()))
             .app_data(repo.clone())
+            .app_data(bulk_config.clone())
+            .app_data(cache_config.clone())
             .configure(v1::service::<PostgresRepository>)
             .configure(health::service)
+            .configure(metrics::service)
     })
     .bind(&address)
     .unwrap_or_else(|err| {
@@ -54,7 +93,10 @@ async fn main() -> std::io::Result<()> {
         )
     })
     .run()
-    .await
+    .await?;
+
+    telemetry::shutdown(tracer_provider);
+    Ok(())
 }
 
 #[cfg(test)]
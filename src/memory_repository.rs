@@ -0,0 +1,365 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use uuid::Uuid;
+
+use crate::bulk::{BulkItemResult, BulkMode, BulkOperation};
+use crate::create_user::CreateUser;
+use crate::custom_data::CustomDataSchema;
+use crate::custom_data_filter::CustomDataFilter;
+use crate::custom_field::CustomField;
+use crate::email::EmailRules;
+use crate::error::Error;
+use crate::filter_expression::FilterExpression;
+use crate::import_job::{ImportBatch, ImportJob, ImportStatus};
+use crate::page::{CountMode, Page, Total};
+use crate::patch_user::PatchUser;
+use crate::repository::{Repository, RepositoryResult, RepositoryResultList};
+use crate::search::{self, SearchHit, SearchQuery};
+use crate::user::User;
+
+/// Same default as `pg_trgm.word_similarity_threshold`, used by the `<%` operator.
+const WORD_SIMILARITY_THRESHOLD: f32 = 0.6;
+/// Rank of a full-text match without a similar word, below any similarity match.
+const PREFIX_MATCH_RANK: f32 = 0.1;
+
+/// Repository keeping users in memory, for tests exercising behaviour a mock would have to
+/// reimplement, like search. Imports, custom fields and bulk operations are not supported.
+#[derive(Default)]
+pub struct InMemoryRepository {
+    users: Mutex<Vec<User>>,
+}
+
+impl InMemoryRepository {
+    pub fn with_users(users: Vec<User>) -> Self {
+        Self { users: Mutex::new(users) }
+    }
+
+    fn users(&self) -> std::sync::MutexGuard<'_, Vec<User>> {
+        self.users.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn update_in(users: &mut [User], user: &User) -> RepositoryResult<User> {
+        CustomDataSchema::default().validate(&user.custom_data)?;
+        let email = Self::checked_email(users, &user.email, &user.id)?;
+        let stored = users.iter_mut().find(|stored| stored.id == user.id).ok_or_else(not_found)?;
+        *stored = User { email, updated_at: Some(Utc::now()), created_at: stored.created_at, ..user.clone() };
+        Ok(stored.clone())
+    }
+
+    /// Normalizes the email of a user to store, rejecting one already used by another user.
+    fn checked_email(users: &[User], email: &str, user_id: &Uuid) -> RepositoryResult<String> {
+        let email = EmailRules::default().normalize(email)?;
+        if users.iter().any(|user| user.id != *user_id && user.email.to_lowercase() == email.to_lowercase()) {
+            return Err(Error::new("A user with this email already exists".to_string(), 409)
+                .with_field("email".to_string()));
+        }
+        Ok(email)
+    }
+}
+
+fn not_found() -> Error {
+    Error::new("This user does not exist".to_string(), 404)
+}
+
+fn unsupported<T>(method: &str) -> RepositoryResult<T> {
+    Err(Error::new(format!("{} is not supported in memory", method), 501))
+}
+
+/// Trigrams of a text the way `pg_trgm` extracts them: per word, padded with two spaces before
+/// and one after.
+fn trigrams(text: &str) -> HashSet<String> {
+    let mut trigrams = HashSet::new();
+    for word in search::terms(text) {
+        let padded: Vec<char> = format!("  {} ", word).chars().collect();
+        for window in padded.windows(3) {
+            trigrams.insert(window.iter().collect());
+        }
+    }
+    trigrams
+}
+
+/// Share of the trigrams of `query` found in the best matching word of `text`, an
+/// approximation of `pg_trgm`'s `word_similarity`.
+fn word_similarity(query: &str, text: &str) -> f32 {
+    let query = trigrams(query);
+    if query.is_empty() {
+        return 0.0;
+    }
+    search::terms(text)
+        .iter()
+        .map(|word| query.intersection(&trigrams(word)).count() as f32 / query.len() as f32)
+        .fold(0.0, f32::max)
+}
+
+/// Whether every search term starts a word of the user, like the full-text prefix query.
+fn prefix_match(user: &User, query: &SearchQuery) -> bool {
+    let words = search::terms(&format!("{} {}", user.name, user.email));
+    search::terms(&query.q)
+        .iter()
+        .all(|term| words.iter().any(|word| word.starts_with(term.as_str())))
+}
+
+#[async_trait]
+impl Repository for InMemoryRepository {
+    async fn get_all(&self) -> RepositoryResultList<User> {
+        Ok(self.users().clone())
+    }
+
+    async fn get_page(&self, page: &Page) -> RepositoryResultList<User> {
+        let mut users = self.users().clone();
+        users.sort_by_key(|user| user.id);
+        Ok(users.into_iter().skip(page.offset as usize).take(page.limit as usize).collect())
+    }
+
+    async fn count_users(&self, _mode: CountMode) -> RepositoryResult<Total> {
+        Ok(Total::exact(self.users().len()))
+    }
+
+    fn export_users(&self) -> BoxStream<'_, RepositoryResult<User>> {
+        stream::iter(self.users().clone().into_iter().map(Ok)).boxed()
+    }
+
+    async fn get_user(&self, user_id: &Uuid) -> RepositoryResult<User> {
+        self.users().iter().find(|user| user.id == *user_id).cloned().ok_or_else(not_found)
+    }
+
+    async fn get_users(&self, user_ids: &[Uuid]) -> RepositoryResultList<User> {
+        Ok(self.users().iter().filter(|user| user_ids.contains(&user.id)).cloned().collect())
+    }
+
+    async fn get_user_by_email(&self, user_email: &str) -> RepositoryResult<User> {
+        let email = EmailRules::default().normalize(user_email)?.to_lowercase();
+        self.users()
+            .iter()
+            .find(|user| user.email.to_lowercase() == email)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn get_users_by_custom_data(&self, _filters: &[CustomDataFilter]) -> RepositoryResultList<User> {
+        unsupported("get_users_by_custom_data")
+    }
+
+    async fn get_users_by_filter(&self, _filter: &FilterExpression) -> RepositoryResultList<User> {
+        unsupported("get_users_by_filter")
+    }
+
+    async fn search_users(&self, query: &SearchQuery) -> RepositoryResultList<SearchHit> {
+        let mut hits: Vec<SearchHit> = self
+            .users()
+            .iter()
+            .filter_map(|user| {
+                let similarity = word_similarity(&query.q, &user.name)
+                    .max(word_similarity(&query.q, &user.email));
+                let rank = if similarity >= WORD_SIMILARITY_THRESHOLD {
+                    similarity
+                } else if prefix_match(user, query) {
+                    PREFIX_MATCH_RANK
+                } else {
+                    return None;
+                };
+                Some(SearchHit::new(user.clone(), rank, query))
+            })
+            .collect();
+        hits.sort_by(|a, b| b.rank.total_cmp(&a.rank).then(a.user.id.cmp(&b.user.id)));
+        hits.truncate(query.limit() as usize);
+        Ok(hits)
+    }
+
+    async fn create_user(&self, user: &CreateUser) -> RepositoryResult<User> {
+        CustomDataSchema::default().validate(&user.custom_data)?;
+        let mut users = self.users();
+        let id = Uuid::new_v4();
+        let user = User {
+            id,
+            email: Self::checked_email(&users, &user.email, &id)?,
+            name: user.name.clone(),
+            birth_date: user.birth_date,
+            custom_data: user.custom_data.clone(),
+            created_at: Some(Utc::now()),
+            updated_at: None,
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn update_user(&self, user: &User) -> RepositoryResult<User> {
+        Self::update_in(&mut self.users(), user)
+    }
+
+    async fn upsert_user(&self, user: &User) -> RepositoryResult<(User, bool)> {
+        let exists = self.users().iter().any(|stored| stored.id == user.id);
+        if exists {
+            return self.update_user(user).await.map(|user| (user, false));
+        }
+
+        CustomDataSchema::default().validate(&user.custom_data)?;
+        let mut users = self.users();
+        let email = Self::checked_email(&users, &user.email, &user.id)?;
+        let user = User { email, created_at: Some(Utc::now()), updated_at: None, ..user.clone() };
+        users.push(user.clone());
+        Ok((user, true))
+    }
+
+    async fn patch_user(
+        &self,
+        user_id: &Uuid,
+        changes: &PatchUser,
+        updated_at: Option<DateTime<Utc>>,
+    ) -> RepositoryResult<User> {
+        let mut users = self.users();
+        let mut user = users.iter().find(|user| user.id == *user_id).cloned().ok_or_else(not_found)?;
+        if user.updated_at != updated_at {
+            return Err(PatchUser::stale());
+        }
+        if let Some(email) = &changes.email {
+            user.email = email.clone();
+        }
+        if let Some(name) = &changes.name {
+            user.name = name.clone();
+        }
+        if let Some(birth_date) = changes.birth_date {
+            user.birth_date = birth_date;
+        }
+        if let Some(custom_data) = &changes.custom_data {
+            user.custom_data = custom_data.clone();
+        }
+        Self::update_in(&mut users, &user)
+    }
+
+    async fn delete_user(&self, user_id: &Uuid) -> RepositoryResult<Uuid> {
+        let mut users = self.users();
+        let index = users.iter().position(|user| user.id == *user_id).ok_or_else(not_found)?;
+        Ok(users.remove(index).id)
+    }
+
+    async fn bulk(&self, _operations: &[BulkOperation], _mode: BulkMode) -> RepositoryResultList<BulkItemResult> {
+        unsupported("bulk")
+    }
+
+    async fn create_import(&self, _data: &[u8], _total_rows: i64) -> RepositoryResult<ImportJob> {
+        unsupported("create_import")
+    }
+
+    async fn get_import(&self, _import_id: &Uuid) -> RepositoryResult<ImportJob> {
+        unsupported("get_import")
+    }
+
+    async fn cancel_import(&self, _import_id: &Uuid) -> RepositoryResult<ImportJob> {
+        unsupported("cancel_import")
+    }
+
+    async fn claim_import(&self, _lease: Duration) -> RepositoryResult<Option<(ImportJob, Vec<u8>)>> {
+        Ok(None)
+    }
+
+    async fn import_batch(&self, _import_id: &Uuid, _batch: &ImportBatch) -> RepositoryResult<ImportJob> {
+        unsupported("import_batch")
+    }
+
+    async fn finish_import(
+        &self,
+        _import_id: &Uuid,
+        _status: ImportStatus,
+        _error: Option<String>,
+    ) -> RepositoryResult<ImportJob> {
+        unsupported("finish_import")
+    }
+
+    async fn list_custom_fields(&self) -> RepositoryResultList<CustomField> {
+        Ok(Vec::new())
+    }
+
+    async fn get_custom_field(&self, _name: &str) -> RepositoryResult<CustomField> {
+        Err(Error::new("This custom field does not exist".to_string(), 404))
+    }
+
+    async fn create_custom_field(&self, _field: &CustomField) -> RepositoryResult<CustomField> {
+        unsupported("create_custom_field")
+    }
+
+    async fn update_custom_field(&self, _field: &CustomField) -> RepositoryResult<CustomField> {
+        unsupported("update_custom_field")
+    }
+
+    async fn delete_custom_field(&self, _name: &str) -> RepositoryResult<String> {
+        unsupported("delete_custom_field")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::create_test_user;
+
+    fn user(name: &str, email: &str) -> User {
+        User { email: email.to_string(), ..create_test_user(Uuid::new_v4(), name.to_string(), (1977, 3, 10)) }
+    }
+
+    fn repository() -> InMemoryRepository {
+        InMemoryRepository::with_users(vec![
+            user("Anabela Souza", "bela@teste.com"),
+            user("Ana Lima", "lima@teste.com"),
+            user("Bruno Costa", "ana.costa@teste.com"),
+            user("Carla Dias", "carla@teste.com"),
+        ])
+    }
+
+    fn search(q: &str) -> SearchQuery {
+        SearchQuery { q: q.to_string(), limit: None }
+    }
+
+    #[test]
+    fn word_similarity_tolerates_prefixes_and_typos() {
+        assert!(word_similarity("ana", "Anabela Souza") >= WORD_SIMILARITY_THRESHOLD);
+        assert!(word_similarity("anabella", "Anabela Souza") >= WORD_SIMILARITY_THRESHOLD);
+        assert!(word_similarity("ana", "Bruno Lima") < WORD_SIMILARITY_THRESHOLD);
+        assert_eq!(word_similarity("", "Bruno"), 0.0);
+    }
+
+    #[actix_rt::test]
+    async fn search_finds_partial_names_best_first() {
+        let hits = repository().search_users(&search("ana")).await.unwrap();
+        let names: Vec<&str> = hits.iter().map(|hit| hit.user.name.as_str()).collect();
+        assert_eq!(names.len(), 3);
+        assert!(!names.contains(&"Carla Dias"));
+        assert!(hits.windows(2).all(|pair| pair[0].rank >= pair[1].rank));
+        let ana = hits.iter().find(|hit| hit.user.name == "Ana Lima").unwrap();
+        assert_eq!(ana.highlights.name, "<mark>Ana</mark> Lima");
+    }
+
+    #[actix_rt::test]
+    async fn search_tolerates_typos() {
+        let hits = repository().search_users(&search("anabella")).await.unwrap();
+        assert_eq!(hits[0].user.name, "Anabela Souza");
+    }
+
+    #[actix_rt::test]
+    async fn search_respects_the_limit() {
+        let query = SearchQuery { limit: Some(1), ..search("ana") };
+        assert_eq!(repository().search_users(&query).await.unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn create_rejects_emails_in_use() {
+        let repo = repository();
+        let mut user = CreateUser {
+            email: "BELA@teste.com".to_string(),
+            name: "Bela".to_string(),
+            birth_date: chrono::NaiveDate::from_ymd(1977, 3, 10),
+            custom_data: crate::custom_data::empty(),
+            created_at: None,
+            updated_at: None,
+        };
+        assert_eq!(repo.create_user(&user).await.unwrap_err().status, 409);
+
+        user.email = "nova@teste.com".to_string();
+        let created = repo.create_user(&user).await.unwrap();
+        assert_eq!(repo.get_user_by_email("Nova@Teste.com").await.unwrap().id, created.id);
+    }
+}
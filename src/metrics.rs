@@ -0,0 +1,434 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::{self, ServiceConfig};
+use actix_web::HttpResponse;
+use futures::future::LocalBoxFuture;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use tracing::instrument;
+
+use crate::repository::RepositoryResult;
+
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    repository_duration: HistogramVec,
+    repository_errors: IntCounterVec,
+    repository_in_flight: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Total number of HTTP requests"),
+            &["method", "route", "status"],
+        )
+        .expect("valid http_requests_total metric");
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency in seconds",
+            ),
+            &["method", "route", "status"],
+        )
+        .expect("valid http_request_duration_seconds metric");
+        let repository_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "repository_call_duration_seconds",
+                "Repository call latency in seconds",
+            ),
+            &["method"],
+        )
+        .expect("valid repository_call_duration_seconds metric");
+        let repository_errors = IntCounterVec::new(
+            Opts::new("repository_errors_total", "Total number of failed repository calls"),
+            &["method", "status"],
+        )
+        .expect("valid repository_errors_total metric");
+        let repository_in_flight = IntGauge::new(
+            "repository_calls_in_flight",
+            "Repository calls currently running",
+        )
+        .expect("valid repository_calls_in_flight metric");
+
+        registry.register(Box::new(http_requests.clone())).expect("register http_requests_total");
+        registry.register(Box::new(http_duration.clone())).expect("register http_request_duration_seconds");
+        registry.register(Box::new(repository_duration.clone())).expect("register repository_call_duration_seconds");
+        registry.register(Box::new(repository_errors.clone())).expect("register repository_errors_total");
+        registry.register(Box::new(repository_in_flight.clone())).expect("register repository_calls_in_flight");
+
+        Self {
+            registry,
+            http_requests,
+            http_duration,
+            repository_duration,
+            repository_errors,
+            repository_in_flight,
+        }
+    }
+
+    /// Registers gauges for the given pool, read every time the registry is scraped.
+    pub fn register_pool(&self, pool: sqlx::PgPool) {
+        let collector = PoolCollector::new(pool);
+        if let Err(err) = self.registry.register(Box::new(collector)) {
+            tracing::error!("Error on register database pool metrics: {:?}", err);
+        }
+    }
+
+    /// Runs a repository call, recording its latency and, on failure, its status.
+    pub async fn observe_repository<T, F>(&self, method: &str, call: F) -> RepositoryResult<T>
+    where
+        F: Future<Output = RepositoryResult<T>>,
+    {
+        let in_flight = InFlight::start(&self.repository_in_flight);
+        let start = Instant::now();
+        let result = call.await;
+        drop(in_flight);
+
+        self.repository_duration
+            .with_label_values(&[method])
+            .observe(start.elapsed().as_secs_f64());
+        if let Err(err) = &result {
+            self.repository_errors
+                .with_label_values(&[method, &err.status.to_string()])
+                .inc();
+        }
+        result
+    }
+
+    /// Records a repository stream like a call, from its creation until it ends or is dropped,
+    /// counting each error it yields.
+    pub fn observe_repository_stream<'a, T: Send + 'a>(
+        &self,
+        method: &str,
+        stream: BoxStream<'a, RepositoryResult<T>>,
+    ) -> BoxStream<'a, RepositoryResult<T>> {
+        ObservedStream {
+            stream,
+            metrics: self.clone(),
+            method: method.to_string(),
+            start: Instant::now(),
+            _in_flight: InFlight::start(&self.repository_in_flight),
+        }
+        .boxed()
+    }
+
+    fn observe_request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_duration.with_label_values(&labels).observe(seconds);
+    }
+
+    fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts a call as running until dropped, so calls abandoned when a client disconnects or a
+/// timeout cancels them stop being counted too.
+struct InFlight(IntGauge);
+
+impl InFlight {
+    fn start(gauge: &IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// A repository stream, timed until dropped.
+struct ObservedStream<'a, T> {
+    stream: BoxStream<'a, RepositoryResult<T>>,
+    metrics: Metrics,
+    method: String,
+    start: Instant,
+    _in_flight: InFlight,
+}
+
+impl<T> Stream for ObservedStream<'_, T> {
+    type Item = RepositoryResult<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = self.stream.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Err(err))) = &item {
+            self.metrics
+                .repository_errors
+                .with_label_values(&[&self.method, &err.status.to_string()])
+                .inc();
+        }
+        item
+    }
+}
+
+impl<T> Drop for ObservedStream<'_, T> {
+    fn drop(&mut self) {
+        self.metrics
+            .repository_duration
+            .with_label_values(&[&self.method])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Exposes `db_pool_size` and `db_pool_idle`.
+///
+/// sqlx does not report how many tasks are queued on `acquire`, and repository calls in flight
+/// cannot stand in for them: nested calls and open export streams are counted too, and a call
+/// does not hold a connection for its whole duration. No waiting gauge is exposed rather than a
+/// misleading one.
+struct PoolCollector {
+    pool: sqlx::PgPool,
+    size: IntGauge,
+    idle: IntGauge,
+    descs: Vec<Desc>,
+}
+
+impl PoolCollector {
+    fn new(pool: sqlx::PgPool) -> Self {
+        let size = IntGauge::new("db_pool_size", "Connections currently opened by the pool")
+            .expect("valid db_pool_size metric");
+        let idle = IntGauge::new("db_pool_idle", "Idle connections in the pool")
+            .expect("valid db_pool_idle metric");
+        let descs = [&size, &idle]
+            .iter()
+            .flat_map(|gauge| gauge.desc().into_iter().cloned())
+            .collect();
+        Self { pool, size, idle, descs }
+    }
+}
+
+impl Collector for PoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.size.set(self.pool.size() as i64);
+        self.idle.set(self.pool.num_idle() as i64);
+
+        [&self.size, &self.idle]
+            .iter()
+            .flat_map(|gauge| gauge.collect())
+            .collect()
+    }
+}
+
+#[instrument(skip(cfg), level = "trace")]
+pub fn service(cfg: &mut ServiceConfig) {
+    tracing::trace!("Init metrics service");
+    cfg.route("/metrics", web::get().to(metrics));
+}
+
+async fn metrics(metrics: web::Data<Metrics>) -> HttpResponse {
+    match metrics.encode() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(TextEncoder::new().format_type())
+            .body(body),
+        Err(err) => {
+            tracing::error!("Error on encode metrics: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Middleware counting requests and their latency by method, route template and status.
+pub struct HttpMetrics {
+    metrics: Arc<Metrics>,
+}
+
+impl HttpMetrics {
+    pub fn new(metrics: Metrics) -> Self {
+        Self { metrics: Arc::new(metrics) }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for HttpMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = HttpMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HttpMetricsMiddleware {
+            service,
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+pub struct HttpMetricsMiddleware<S> {
+    service: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B> Service<ServiceRequest> for HttpMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+        let metrics = self.metrics.clone();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            let status = match &result {
+                Ok(res) => res.status().as_u16(),
+                Err(err) => err.as_response_error().status_code().as_u16(),
+            };
+            metrics.observe_request(&method, &route, status, start.elapsed().as_secs_f64());
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use actix_web::http::StatusCode;
+    use actix_web::App;
+
+    #[actix_rt::test]
+    async fn metrics_counts_requests_by_route_template() {
+        let metrics = Metrics::new();
+        let app = App::new()
+            .wrap(HttpMetrics::new(metrics.clone()))
+            .app_data(web::Data::new(metrics.clone()))
+            .route("/v1/user/{user_id}", web::get().to(HttpResponse::Ok))
+            .configure(service);
+        let app = actix_web::test::init_service(app).await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/v1/user/71802ecd-4eb3-4381-af7e-f737e3a35d5d")
+            .to_request();
+        actix_web::test::call_service(&app, req).await;
+
+        let req = actix_web::test::TestRequest::get().uri("/metrics").to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = actix_web::test::read_body(res).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(
+            r#"http_requests_total{method="GET",route="/v1/user/{user_id}",status="200"} 1"#
+        ));
+        assert!(body.contains(
+            r#"http_request_duration_seconds_count{method="GET",route="/v1/user/{user_id}",status="200"} 1"#
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn metrics_labels_unknown_routes_as_unmatched() {
+        let metrics = Metrics::new();
+        let app = App::new().wrap(HttpMetrics::new(metrics.clone()));
+        let app = actix_web::test::init_service(app).await;
+
+        let req = actix_web::test::TestRequest::get().uri("/unknown/path").to_request();
+        actix_web::test::call_service(&app, req).await;
+
+        let body = metrics.encode().unwrap();
+        assert!(body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+    }
+
+    #[actix_rt::test]
+    async fn observe_repository_records_latency_and_errors() {
+        let metrics = Metrics::new();
+
+        let ok: RepositoryResult<()> = metrics.observe_repository("get_all", async { Ok(()) }).await;
+        assert!(ok.is_ok());
+        let err: RepositoryResult<()> = metrics
+            .observe_repository("get_user", async { Err(Error::new("error".to_string(), 404)) })
+            .await;
+        assert!(err.is_err());
+
+        let body = metrics.encode().unwrap();
+        assert!(body.contains(r#"repository_call_duration_seconds_count{method="get_all"} 1"#));
+        assert!(body.contains(r#"repository_call_duration_seconds_count{method="get_user"} 1"#));
+        assert!(body.contains(r#"repository_errors_total{method="get_user",status="404"} 1"#));
+        assert!(!body.contains(r#"repository_errors_total{method="get_all""#));
+    }
+
+    #[test]
+    fn abandoned_repository_calls_leave_the_in_flight_gauge() {
+        let metrics = Metrics::new();
+        let call = metrics.observe_repository("get_all", futures::future::pending::<RepositoryResult<()>>());
+        assert!(futures::FutureExt::now_or_never(call).is_none());
+        assert_eq!(metrics.repository_in_flight.get(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn observe_repository_stream_lasts_until_dropped() {
+        let metrics = Metrics::new();
+        let items: Vec<RepositoryResult<u8>> = vec![Ok(1), Err(Error::new("error".to_string(), 502))];
+        let mut stream = metrics.observe_repository_stream("export_users", futures::stream::iter(items).boxed());
+
+        assert!(stream.next().await.unwrap().is_ok());
+        assert_eq!(metrics.repository_in_flight.get(), 1);
+        assert!(stream.next().await.unwrap().is_err());
+        drop(stream);
+
+        assert_eq!(metrics.repository_in_flight.get(), 0);
+        let body = metrics.encode().unwrap();
+        assert!(body.contains(r#"repository_call_duration_seconds_count{method="export_users"} 1"#));
+        assert!(body.contains(r#"repository_errors_total{method="export_users",status="502"} 1"#));
+    }
+
+    #[actix_rt::test]
+    async fn metrics_exposes_pool_gauges() {
+        let metrics = Metrics::new();
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/metrics").unwrap();
+        metrics.register_pool(pool);
+
+        let body = metrics.encode().unwrap();
+        assert!(body.contains("db_pool_size 0"));
+        assert!(body.contains("db_pool_idle 0"));
+        assert!(!body.contains("db_pool_waiting"));
+    }
+}
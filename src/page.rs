@@ -0,0 +1,113 @@
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+/// Largest offset, so the offset of the next page, `offset + limit`, still fits an `i64`.
+const MAX_OFFSET: i64 = i64::MAX - MAX_LIMIT;
+
+/// How the total of a collection is computed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CountMode {
+    /// `count(*)`, which scans the table.
+    #[default]
+    Exact,
+    /// The row estimate Postgres keeps in its statistics, immediate even on large tables.
+    Estimated,
+}
+
+/// A window of a collection ordered by id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Number of items in a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Total {
+    pub count: i64,
+    /// Whether `count` comes from statistics and may be off.
+    pub estimated: bool,
+}
+
+impl Total {
+    pub fn exact(count: usize) -> Self {
+        Self { count: count as i64, estimated: false }
+    }
+}
+
+/// Query parameters paginating and wrapping a collection, e.g. `?limit=20&offset=40&envelope=true`.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// Wraps the items with their total, page and links instead of returning a bare array.
+    #[serde(default)]
+    pub envelope: bool,
+    #[serde(default)]
+    pub count: CountMode,
+}
+
+impl PageQuery {
+    pub fn from_query(query: &str) -> Result<Self, Error> {
+        web::Query::<PageQuery>::from_query(query)
+            .map(web::Query::into_inner)
+            .map_err(|e| Error::new(format!("Invalid pagination: {}", e), 400))
+    }
+
+    /// The requested page, `None` for the whole collection.
+    pub fn page(&self) -> Result<Option<Page>, Error> {
+        if self.limit.is_none() && self.offset.is_none() {
+            return Ok(None);
+        }
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            let message = format!("Limit must be between 1 and {}, got {}", MAX_LIMIT, limit);
+            return Err(Error::new(message, 400).with_field("limit".to_string()));
+        }
+        let offset = self.offset.unwrap_or(0);
+        if offset < 0 {
+            return Err(Error::new("Offset must not be negative".to_string(), 400).with_field("offset".to_string()));
+        }
+        if offset > MAX_OFFSET {
+            let message = format!("Offset must be at most {}, got {}", MAX_OFFSET, offset);
+            return Err(Error::new(message, 400).with_field("offset".to_string()));
+        }
+        Ok(Some(Page { limit, offset }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_defaults_the_limit_and_offset() {
+        assert_eq!(PageQuery::from_query("email=a@b.com").unwrap().page().unwrap(), None);
+        let page = PageQuery::from_query("offset=40").unwrap().page().unwrap();
+        assert_eq!(page, Some(Page { limit: DEFAULT_LIMIT, offset: 40 }));
+        let query = PageQuery::from_query("limit=20&envelope=true&count=estimated").unwrap();
+        assert_eq!(query.page().unwrap(), Some(Page { limit: 20, offset: 0 }));
+        assert!(query.envelope);
+        assert_eq!(query.count, CountMode::Estimated);
+    }
+
+    #[test]
+    fn page_rejects_out_of_range_values() {
+        let err = PageQuery::from_query("limit=0").unwrap().page().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("limit"));
+        let err = PageQuery::from_query("limit=1001").unwrap().page().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("limit"));
+        let err = PageQuery::from_query("offset=-1").unwrap().page().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("offset"));
+        let err = PageQuery::from_query("limit=10&offset=9223372036854775807").unwrap().page().unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (400, Some("offset")));
+        let page = PageQuery::from_query(&format!("limit={}&offset={}", MAX_LIMIT, MAX_OFFSET)).unwrap().page().unwrap();
+        assert_eq!(page.unwrap().offset + MAX_LIMIT, i64::MAX);
+        assert_eq!(PageQuery::from_query("count=maybe").unwrap_err().status, 400);
+    }
+}
@@ -0,0 +1,220 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::Error;
+
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+pub const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+/// A JSON Patch (RFC 6902) operation.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+/// Applies a JSON Merge Patch (RFC 7396) to `target`.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        patch => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+
+    let target = target.as_object_mut().expect("target is an object");
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.as_str()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Applies a JSON Patch (RFC 6902) to `target`. Either every operation is applied or `target`
+/// is left untouched: a failed `test` returns 409 and an invalid path 422.
+pub fn json_patch(target: &mut Value, operations: &[PatchOperation]) -> Result<(), Error> {
+    let mut document = target.clone();
+    for operation in operations {
+        match operation {
+            PatchOperation::Add { path, value } => add(&mut document, path, value.clone())?,
+            PatchOperation::Remove { path } => {
+                remove(&mut document, path)?;
+            }
+            PatchOperation::Replace { path, value } => {
+                *document.pointer_mut(path).ok_or_else(|| path_error(path))? = value.clone();
+            }
+            PatchOperation::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(path_error(path));
+                }
+                let value = remove(&mut document, from)?;
+                add(&mut document, path, value)?;
+            }
+            PatchOperation::Copy { from, path } => {
+                let value = document.pointer(from).cloned().ok_or_else(|| path_error(from))?;
+                add(&mut document, path, value)?;
+            }
+            PatchOperation::Test { path, value } => {
+                if document.pointer(path).ok_or_else(|| path_error(path))? != value {
+                    return Err(Error::new(format!("Test failed at {}", path), 409)
+                        .with_field(path.clone()));
+                }
+            }
+        }
+    }
+    *target = document;
+    Ok(())
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), Error> {
+    if path.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    let (parent, key) = split_pointer(path)?;
+    match document.pointer_mut(parent) {
+        Some(Value::Object(object)) => {
+            object.insert(key, value);
+        }
+        Some(Value::Array(array)) if key == "-" => array.push(value),
+        Some(Value::Array(array)) => {
+            let index = array_index(&key).filter(|index| *index <= array.len());
+            array.insert(index.ok_or_else(|| path_error(path))?, value);
+        }
+        _ => return Err(path_error(path)),
+    }
+    Ok(())
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, Error> {
+    let (parent, key) = split_pointer(path)?;
+    let removed = match document.pointer_mut(parent) {
+        Some(Value::Object(object)) => object.remove(&key),
+        Some(Value::Array(array)) => array_index(&key)
+            .filter(|index| *index < array.len())
+            .map(|index| array.remove(index)),
+        _ => None,
+    };
+    removed.ok_or_else(|| path_error(path))
+}
+
+/// Splits a JSON Pointer into its parent pointer and its unescaped last token.
+fn split_pointer(path: &str) -> Result<(&str, String), Error> {
+    let (parent, key) = path.rsplit_once('/').ok_or_else(|| path_error(path))?;
+    if !parent.is_empty() && !parent.starts_with('/') {
+        return Err(path_error(path));
+    }
+    Ok((parent, key.replace("~1", "/").replace("~0", "~")))
+}
+
+fn array_index(key: &str) -> Option<usize> {
+    if key.len() > 1 && key.starts_with('0') {
+        return None;
+    }
+    key.parse().ok()
+}
+
+fn path_error(path: &str) -> Error {
+    Error::new(format!("Invalid patch path {}", path), 422).with_field(path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn merged(target: Value, patch: Value) -> Value {
+        let mut target = target;
+        merge_patch(&mut target, &patch);
+        target
+    }
+
+    #[test]
+    fn merge_patch_replaces_and_removes_members() {
+        assert_eq!(
+            merged(json!({"a": "b", "c": {"d": "e", "f": "g"}}), json!({"a": "z", "c": {"f": null}})),
+            json!({"a": "z", "c": {"d": "e"}})
+        );
+    }
+
+    #[test]
+    fn merge_patch_rfc_examples() {
+        assert_eq!(merged(json!({"a": "b"}), json!({"a": "c"})), json!({"a": "c"}));
+        assert_eq!(merged(json!({"a": "b"}), json!({"b": "c"})), json!({"a": "b", "b": "c"}));
+        assert_eq!(merged(json!({"a": "b"}), json!({"a": null})), json!({}));
+        assert_eq!(merged(json!({"a": ["b"]}), json!({"a": "c"})), json!({"a": "c"}));
+        assert_eq!(merged(json!({"a": "c"}), json!({"a": ["b"]})), json!({"a": ["b"]}));
+        assert_eq!(merged(json!({"a": {"b": "c"}}), json!({"a": {"b": "d", "c": null}})), json!({"a": {"b": "d"}}));
+        assert_eq!(merged(json!(["a", "b"]), json!(["c", "d"])), json!(["c", "d"]));
+        assert_eq!(merged(json!({"a": "b"}), json!(["c"])), json!(["c"]));
+        assert_eq!(merged(json!({"e": null}), json!({"a": 1})), json!({"e": null, "a": 1}));
+        assert_eq!(merged(json!([1, 2]), json!({"a": "b", "c": null})), json!({"a": "b"}));
+        assert_eq!(merged(json!({}), json!({"a": {"bb": {"ccc": null}}})), json!({"a": {"bb": {}}}));
+    }
+
+    fn patched(target: Value, operations: Value) -> Result<Value, Error> {
+        let mut target = target;
+        let operations: Vec<PatchOperation> = serde_json::from_value(operations).unwrap();
+        json_patch(&mut target, &operations).map(|_| target)
+    }
+
+    #[test]
+    fn json_patch_rfc_examples() {
+        let target = json!({"foo": ["bar", "baz"], "a": {"b": "c"}});
+        let result = patched(target, json!([
+            {"op": "add", "path": "/foo/1", "value": "qux"},
+            {"op": "add", "path": "/foo/-", "value": "end"},
+            {"op": "remove", "path": "/foo/0"},
+            {"op": "replace", "path": "/a/b", "value": 42},
+            {"op": "copy", "from": "/a/b", "path": "/a/d"},
+            {"op": "move", "from": "/a/d", "path": "/e~1f"},
+            {"op": "test", "path": "/foo", "value": ["qux", "baz", "end"]},
+        ]));
+        assert_eq!(result.unwrap(), json!({"foo": ["qux", "baz", "end"], "a": {"b": 42}, "e/f": 42}));
+    }
+
+    #[test]
+    fn json_patch_failed_test_is_a_conflict() {
+        let err = patched(json!({"a": 1}), json!([{"op": "test", "path": "/a", "value": 2}])).unwrap_err();
+        assert_eq!(err.status, 409);
+        assert_eq!(err.field.as_deref(), Some("/a"));
+    }
+
+    #[test]
+    fn json_patch_rejects_invalid_paths() {
+        for operations in [
+            json!([{"op": "remove", "path": "/missing"}]),
+            json!([{"op": "replace", "path": "/a/b", "value": 1}]),
+            json!([{"op": "add", "path": "/list/5", "value": 1}]),
+            json!([{"op": "add", "path": "/list/01", "value": 1}]),
+            json!([{"op": "add", "path": "a", "value": 1}]),
+            json!([{"op": "move", "from": "/list", "path": "/list/0"}]),
+        ] {
+            let err = patched(json!({"a": 1, "list": [1]}), operations).unwrap_err();
+            assert_eq!(err.status, 422);
+        }
+    }
+
+    #[test]
+    fn json_patch_is_atomic() {
+        let mut target = json!({"a": 1});
+        let operations: Vec<PatchOperation> = serde_json::from_value(json!([
+            {"op": "replace", "path": "/a", "value": 2},
+            {"op": "test", "path": "/a", "value": 3},
+        ]))
+        .unwrap();
+        assert!(json_patch(&mut target, &operations).is_err());
+        assert_eq!(target, json!({"a": 1}));
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::NaiveDate;
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::patch::{json_patch, merge_patch, PatchOperation};
+use crate::custom_data::CustomData;
+use crate::user::User;
+
+/// Fields clients cannot change through a patch.
+const READ_ONLY_FIELDS: &[&str] = &["id", "created_at", "updated_at"];
+
+/// Fields to change on a user; `None` keeps the stored value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PatchUser {
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub birth_date: Option<NaiveDate>,
+    pub custom_data: Option<CustomData>,
+}
+
+impl PatchUser {
+    /// Takes from `patched` the fields that differ from `user`.
+    pub fn changes(user: &User, patched: &User) -> Self {
+        Self {
+            email: (user.email != patched.email).then(|| patched.email.clone()),
+            name: (user.name != patched.name).then(|| patched.name.clone()),
+            birth_date: (user.birth_date != patched.birth_date).then_some(patched.birth_date),
+            custom_data: (user.custom_data != patched.custom_data).then(|| patched.custom_data.clone()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Applies a JSON Merge Patch to `user`, returning the validated result and the changes to
+    /// store.
+    pub fn merge(user: &User, patch: &Value) -> Result<(User, Self), Error> {
+        if !patch.is_object() {
+            return Err(Error::new("Merge patch must be a JSON object".to_string(), 422));
+        }
+
+        let mut document = serde_json::to_value(user)
+            .map_err(|e| Error::new(format!("Error on serialize user: {}", e), 500))?;
+        merge_patch(&mut document, patch);
+        let merged = Self::checked(user, document)?;
+
+        let changes = Self::changes(user, &merged);
+        Ok((merged, changes))
+    }
+
+    /// Applies a JSON Patch to `user`, returning the validated result and the changes to store.
+    pub fn apply(user: &User, patch: &Value) -> Result<(User, Self), Error> {
+        let operations: Vec<PatchOperation> = serde_json::from_value(patch.clone())
+            .map_err(|e| Error::new(format!("Invalid JSON Patch: {}", e), 422))?;
+
+        let mut document = serde_json::to_value(user)
+            .map_err(|e| Error::new(format!("Error on serialize user: {}", e), 500))?;
+        json_patch(&mut document, &operations)?;
+        let patched = Self::checked(user, document)?;
+
+        let changes = Self::changes(user, &patched);
+        Ok((patched, changes))
+    }
+
+    /// The changes were computed from a user another request changed since.
+    pub fn stale() -> Error {
+        Error::new("This user changed since it was read, retry the patch".to_string(), 409)
+    }
+
+    /// Deserializes a patched document, rejecting invalid users, read-only field changes and
+    /// members a user does not have.
+    pub fn checked(user: &User, document: Value) -> Result<User, Error> {
+        let original = serde_json::to_value(user)
+            .map_err(|e| Error::new(format!("Error on serialize user: {}", e), 500))?;
+        for field in READ_ONLY_FIELDS {
+            if original.get(field) != document.get(field) {
+                return Err(Error::new(format!("Field {} cannot be changed", field), 422)
+                    .with_field(field.to_string()));
+            }
+        }
+        if let (Some(original), Some(document)) = (original.as_object(), document.as_object()) {
+            if let Some(unknown) = document.keys().find(|key| !original.contains_key(*key)) {
+                return Err(Error::new(format!("Field {} does not exist", unknown), 422).with_field(unknown.clone()));
+            }
+        }
+
+        let merged: User = serde_json::from_value(document)
+            .map_err(|e| Error::new(format!("Invalid user: {}", e), 422))?;
+        merged.validate()?;
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::create_test_user;
+    use serde_json::json;
+
+    fn user() -> User {
+        create_test_user(uuid::Uuid::new_v4(), "Meu nome".to_string(), (1977, 3, 10))
+    }
+
+    #[test]
+    fn merge_changes_only_supplied_fields() {
+        let (merged, changes) = PatchUser::merge(&user(), &json!({"name": "Outro nome"})).unwrap();
+        assert_eq!(merged.name, "Outro nome");
+        assert_eq!(
+            changes,
+            PatchUser { name: Some("Outro nome".to_string()), ..PatchUser::default() }
+        );
+    }
+
+    #[test]
+    fn merge_updates_nested_custom_data() {
+        let (_, changes) = PatchUser::merge(&user(), &json!({"custom_data": {"random": 7}})).unwrap();
+        assert_eq!(changes.custom_data, Some(json!({"random": 7})));
+    }
+
+    #[test]
+    fn merge_rejects_removing_required_fields() {
+        let err = PatchUser::merge(&user(), &json!({"name": null})).unwrap_err();
+        assert_eq!(err.status, 422);
+    }
+
+    #[test]
+    fn merge_rejects_invalid_values() {
+        let err = PatchUser::merge(&user(), &json!({"name": "  "})).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("name"));
+        let err = PatchUser::merge(&user(), &json!({"email": "teste"})).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("email"));
+        let err = PatchUser::merge(&user(), &json!({"birth_date": "3000-01-01"})).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("birth_date"));
+    }
+
+    #[test]
+    fn merge_ignores_unchanged_values() {
+        let user = user();
+        let (_, changes) = PatchUser::merge(&user, &json!({"name": user.name})).unwrap();
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn apply_changes_nested_custom_data() {
+        let patch = json!([
+            {"op": "test", "path": "/custom_data/random", "value": 1},
+            {"op": "replace", "path": "/custom_data/random", "value": 7},
+        ]);
+        let (patched, changes) = PatchUser::apply(&user(), &patch).unwrap();
+        assert_eq!(patched.custom_data, json!({"random": 7}));
+        assert_eq!(
+            changes,
+            PatchUser { custom_data: Some(json!({"random": 7})), ..PatchUser::default() }
+        );
+    }
+
+    #[test]
+    fn apply_rejects_failed_tests_paths_and_invalid_users() {
+        let test = json!([{"op": "test", "path": "/name", "value": "Outro nome"}]);
+        assert_eq!(PatchUser::apply(&user(), &test).unwrap_err().status, 409);
+        let path = json!([{"op": "replace", "path": "/nickname", "value": "x"}]);
+        assert_eq!(PatchUser::apply(&user(), &path).unwrap_err().status, 422);
+        let remove = json!([{"op": "remove", "path": "/email"}]);
+        assert_eq!(PatchUser::apply(&user(), &remove).unwrap_err().status, 422);
+        let read_only = json!([{"op": "remove", "path": "/created_at"}]);
+        let err = PatchUser::apply(&user(), &read_only).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("created_at"));
+        assert_eq!(PatchUser::apply(&user(), &json!({"op": "remove"})).unwrap_err().status, 422);
+    }
+
+    #[test]
+    fn merge_rejects_unknown_fields() {
+        let err = PatchUser::merge(&user(), &json!({"nickname": "x"})).unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (422, Some("nickname")));
+    }
+
+    #[test]
+    fn apply_rejects_unknown_fields() {
+        let err = PatchUser::apply(&user(), &json!([{"op": "add", "path": "/nickname", "value": "x"}])).unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (422, Some("nickname")));
+    }
+
+    #[test]
+    fn merge_rejects_read_only_fields_and_non_objects() {
+        let err = PatchUser::merge(&user(), &json!({"id": uuid::Uuid::new_v4()})).unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("id"));
+        assert_eq!(PatchUser::merge(&user(), &json!(["name"])).unwrap_err().status, 422);
+    }
+}
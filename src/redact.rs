@@ -0,0 +1,152 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+const MASK: &str = "***";
+
+/// Whether sensitive values may be logged in full.
+///
+/// Only debug builds honour `LOG_SENSITIVE_DATA=true`; release builds always mask.
+pub fn show_sensitive() -> bool {
+    static SHOW: OnceLock<bool> = OnceLock::new();
+    *SHOW.get_or_init(|| {
+        cfg!(debug_assertions)
+            && std::env::var("LOG_SENSITIVE_DATA")
+                .map(|value| value.eq_ignore_ascii_case("true"))
+                .unwrap_or(false)
+    })
+}
+
+/// Masks the wrapped value in `Debug` and `Display` output.
+pub struct Redacted<T>(pub T);
+
+impl<T: fmt::Debug> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if show_sensitive() {
+            fmt::Debug::fmt(&self.0, f)
+        } else {
+            f.write_str(MASK)
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if show_sensitive() {
+            fmt::Display::fmt(&self.0, f)
+        } else {
+            f.write_str(MASK)
+        }
+    }
+}
+
+/// Masks the local part of an email, keeping its first character and the domain.
+pub struct RedactedEmail<'a>(pub &'a str);
+
+impl RedactedEmail<'_> {
+    fn masked(&self) -> String {
+        match self.0.split_once('@') {
+            Some((local, domain)) => {
+                let first = local.chars().next().map(String::from).unwrap_or_default();
+                format!("{}{}@{}", first, MASK, domain)
+            }
+            None => MASK.to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for RedactedEmail<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if show_sensitive() {
+            fmt::Debug::fmt(self.0, f)
+        } else {
+            fmt::Debug::fmt(&self.masked(), f)
+        }
+    }
+}
+
+impl fmt::Display for RedactedEmail<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if show_sensitive() {
+            f.write_str(self.0)
+        } else {
+            f.write_str(&self.masked())
+        }
+    }
+}
+
+/// Formats a sqlx error without the row values Postgres puts in the error detail.
+pub struct RedactedDbError<'a>(pub &'a sqlx::Error);
+
+impl fmt::Debug for RedactedDbError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            sqlx::Error::Database(err) if !show_sensitive() => f
+                .debug_struct("DatabaseError")
+                .field("code", &err.code())
+                .field("message", &err.message())
+                .field("constraint", &err.constraint())
+                .finish(),
+            err => fmt::Debug::fmt(err, f),
+        }
+    }
+}
+
+/// Masks the string literals of a SQL statement recorded on a span. Values are bound rather than
+/// inlined, so this only guards against a statement built with one.
+pub struct RedactedSql<'a>(pub &'a str);
+
+impl fmt::Display for RedactedSql<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if show_sensitive() {
+            return f.write_str(self.0);
+        }
+        let mut in_literal = false;
+        for (index, part) in self.0.split('\'').enumerate() {
+            if index > 0 {
+                f.write_str("'")?;
+                in_literal = !in_literal;
+            }
+            f.write_str(if in_literal { MASK } else { part })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::create_test_user;
+
+    #[test]
+    fn redacted_masks_value() {
+        assert_eq!(format!("{}", Redacted("Meu nome")), "***");
+        assert_eq!(format!("{:?}", Redacted("Meu nome")), "***");
+    }
+
+    #[test]
+    fn redacted_sql_masks_string_literals() {
+        let sql = "SELECT id FROM users WHERE email = 'ana@teste.com' AND name = $1 AND bio = 'it''s'";
+        assert_eq!(
+            RedactedSql(sql).to_string(),
+            "SELECT id FROM users WHERE email = '***' AND name = $1 AND bio = '***''***'"
+        );
+        assert_eq!(RedactedSql("SELECT count(*) FROM users").to_string(), "SELECT count(*) FROM users");
+    }
+
+    #[test]
+    fn redacted_email_keeps_first_char_and_domain() {
+        assert_eq!(format!("{}", RedactedEmail("teste@teste.com")), "t***@teste.com");
+        assert_eq!(format!("{:?}", RedactedEmail("teste@teste.com")), r#""t***@teste.com""#);
+        assert_eq!(format!("{}", RedactedEmail("not-an-email")), "***");
+    }
+
+    #[test]
+    fn user_debug_masks_sensitive_fields() {
+        let user = create_test_user(uuid::Uuid::new_v4(), "Meu nome".to_string(), (1977, 3, 10));
+        let output = format!("{:?}", user);
+        assert!(output.contains(&user.id.to_string()));
+        assert!(!output.contains("teste@teste.com"));
+        assert!(!output.contains("Meu nome"));
+        assert!(!output.contains("1977"));
+    }
+}
@@ -1,166 +1,2282 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use sqlx::{Connection, FromRow, Postgres, Row, Transaction};
+use tracing::Instrument;
 use uuid::Uuid;
 
+use crate::bulk::{operation_error, BulkItemResult, BulkMode, BulkOperation};
 use crate::create_user::CreateUser;
+use crate::custom_data::{CustomData, CustomDataSchema};
+use crate::custom_data_filter::{CustomDataFilter, FilterOperator, FILTER_PREFIX};
+use crate::custom_field::{self, CustomField, CustomFieldType};
+use crate::email::EmailRules;
+use crate::filter_expression::{Comparison, FilterExpression, FilterField, FilterValue, FILTER_PARAMETER};
+use crate::import_job::{ImportBatch, ImportError, ImportJob, ImportStatus, MAX_ERROR_SAMPLES};
+use crate::metrics::Metrics;
+use crate::page::{CountMode, Page, Total};
+use crate::patch_user::PatchUser;
+use crate::redact::{RedactedDbError, RedactedEmail, RedactedSql};
+use crate::search::{SearchHit, SearchQuery};
 use crate::user::{User};
 use crate::Error;
 
 pub type RepositoryResult<T> = Result<T, Error>;
 pub type RepositoryResultList<T> = Result<Vec<T>, Error>;
 
+const UNIQUE_VIOLATION: &str = "23505";
+/// How long custom field definitions are reused by user writes and filters; changes made through
+/// another instance apply after it.
+const CUSTOM_FIELDS_TTL: Duration = Duration::from_secs(30);
+/// Unique indexes, with the resource and the field each one protects.
+const UNIQUE_FIELDS: &[(&str, &str, &str)] = &[
+    ("users_pkey", "A user", "id"),
+    ("users_email", "A user", "email"),
+    ("users_email_lower", "A user", "email"),
+    ("imports_pkey", "An import", "id"),
+    ("custom_fields_pkey", "A custom field", "name"),
+];
+
+const GET_ALL_SQL: &str = "SELECT * FROM users";
+const GET_PAGE_SQL: &str = "SELECT id, name, email, birth_date, custom_data, created_at, updated_at FROM users \
+     ORDER BY id LIMIT $1 OFFSET $2";
+const COUNT_USERS_SQL: &str = "SELECT count(*) FROM users";
+/// Row estimate of the planner statistics, -1 before the table is first vacuumed or analyzed.
+const ESTIMATE_USERS_SQL: &str = "SELECT reltuples::bigint FROM pg_class WHERE oid = 'users'::regclass";
+const GET_USER_SQL: &str =
+    "SELECT id, name, email, birth_date, custom_data, created_at, updated_at FROM users WHERE id = $1";
+const USER_EXISTS_SQL: &str = "SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)";
+const EXPORT_USERS_SQL: &str =
+    "SELECT id, name, email, birth_date, custom_data, created_at, updated_at FROM users";
+const GET_USERS_SQL: &str =
+    "SELECT id, name, email, birth_date, custom_data, created_at, updated_at FROM users WHERE id = ANY($1)";
+/// Users matching filters; [`custom_data_conditions`] or [`filter_expression_query`] append the conditions.
+const GET_FILTERED_USERS_SQL: &str =
+    "SELECT id, name, email, birth_date, custom_data, created_at, updated_at FROM users WHERE";
+/// Full-text prefix matches on name and email, or names and emails with a word similar to the
+/// search (typos), served by the `users_search` and `*_trgm` indexes.
+const SEARCH_USERS_SQL: &str = r#"
+    SELECT id, name, email, birth_date, custom_data, created_at, updated_at,
+        greatest(
+            ts_rank(to_tsvector('simple', name || ' ' || email), to_tsquery('simple', $2)),
+            word_similarity($1, name),
+            word_similarity($1, email)
+        )::real AS rank
+    FROM users
+    WHERE to_tsvector('simple', name || ' ' || email) @@ to_tsquery('simple', $2)
+        OR $1 <% name
+        OR $1 <% email
+    ORDER BY rank DESC, id
+    LIMIT $3
+"#;
+const GET_USER_BY_EMAIL_SQL: &str =
+    "SELECT id, name, email, birth_date, custom_data, created_at, updated_at FROM users WHERE lower(email) = lower($1)";
+const PROVIDER_EMAILS_SQL: &str = r#"
+    SELECT id, email FROM users
+    WHERE lower(substring(email FROM '@([^@]*)$')) = ANY($1)
+    ORDER BY created_at
+    FOR UPDATE
+"#;
+const UPDATE_EMAIL_SQL: &str = "UPDATE users SET email = $2 WHERE id = $1";
+const CREATE_USER_SQL: &str = r#"
+    INSERT INTO users (id, name, email, birth_date, custom_data, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    RETURNING id, name, email, birth_date, custom_data, created_at, updated_at
+"#;
+const UPDATE_USER_SQL: &str = r#"
+    UPDATE users
+    SET custom_data = $1, updated_at = $2, name = $3, email = $4, birth_date = $5
+    WHERE id = $6
+    RETURNING id, name, email, birth_date, custom_data, created_at, updated_at
+"#;
+/// Multi-row insert used by bulk creates; [`create_users_sql`] appends a row of values per user.
+const CREATE_USERS_SQL: &str = "INSERT INTO users (id, name, email, birth_date, custom_data, created_at) VALUES";
+const CREATE_USERS_RETURNING: &str = "RETURNING id, name, email, birth_date, custom_data, created_at, updated_at";
+/// Rows per multi-row insert, keeping its binds under the Postgres limit of 65535.
+const BULK_INSERT_ROWS: usize = 1000;
+const CREATE_IMPORT_SQL: &str = r#"
+    INSERT INTO imports (id, status, data, total_rows, created_at)
+    VALUES ($1, 'pending', $2, $3, $4)
+    RETURNING id, status, total_rows, processed_rows, created_rows, failed_rows, errors, error, created_at, updated_at
+"#;
+const GET_IMPORT_SQL: &str = r#"
+    SELECT id, status, total_rows, processed_rows, created_rows, failed_rows, errors, error, created_at, updated_at
+    FROM imports
+    WHERE id = $1
+"#;
+const LOCK_IMPORT_SQL: &str = r#"
+    SELECT id, status, total_rows, processed_rows, created_rows, failed_rows, errors, error, created_at, updated_at
+    FROM imports
+    WHERE id = $1
+    FOR UPDATE
+"#;
+const CANCEL_IMPORT_SQL: &str = r#"
+    UPDATE imports
+    SET status = 'cancelled', locked_until = NULL, updated_at = $2
+    WHERE id = $1 AND status IN ('pending', 'running')
+    RETURNING id, status, total_rows, processed_rows, created_rows, failed_rows, errors, error, created_at, updated_at
+"#;
+const CLAIM_IMPORT_SQL: &str = r#"
+    UPDATE imports
+    SET status = 'running', locked_until = $1, updated_at = $2
+    WHERE id = (
+        SELECT id FROM imports
+        WHERE status IN ('pending', 'running') AND (locked_until IS NULL OR locked_until < $2)
+        ORDER BY created_at
+        LIMIT 1
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING id, status, total_rows, processed_rows, created_rows, failed_rows, errors, error, created_at, updated_at, data
+"#;
+const UPDATE_IMPORT_PROGRESS_SQL: &str = r#"
+    UPDATE imports
+    SET processed_rows = $2,
+        created_rows = created_rows + $3,
+        failed_rows = failed_rows + $4,
+        errors = errors || $5,
+        locked_until = $6,
+        updated_at = $7
+    WHERE id = $1
+    RETURNING id, status, total_rows, processed_rows, created_rows, failed_rows, errors, error, created_at, updated_at
+"#;
+const FINISH_IMPORT_SQL: &str = r#"
+    UPDATE imports
+    SET status = $2, error = $3, locked_until = NULL, updated_at = $4
+    WHERE id = $1 AND status = 'running'
+    RETURNING id, status, total_rows, processed_rows, created_rows, failed_rows, errors, error, created_at, updated_at
+"#;
+const UPSERT_USER_SQL: &str = r#"
+    INSERT INTO users (id, name, email, birth_date, custom_data, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    ON CONFLICT (id) DO UPDATE
+    SET name = EXCLUDED.name,
+        email = EXCLUDED.email,
+        birth_date = EXCLUDED.birth_date,
+        custom_data = EXCLUDED.custom_data,
+        updated_at = EXCLUDED.created_at
+    RETURNING id, name, email, birth_date, custom_data, created_at, updated_at, (xmax = 0) AS created
+"#;
+const PATCH_USER_SQL: &str = r#"
+    UPDATE users
+    SET email = COALESCE($1, email),
+        name = COALESCE($2, name),
+        birth_date = COALESCE($3, birth_date),
+        custom_data = COALESCE($4, custom_data),
+        updated_at = $5
+    WHERE id = $6 AND updated_at IS NOT DISTINCT FROM $7
+    RETURNING id, name, email, birth_date, custom_data, created_at, updated_at
+"#;
+const LIST_CUSTOM_FIELDS_SQL: &str = r#"
+    SELECT name, type, required, default_value, enum_values, indexed, created_at, updated_at
+    FROM custom_fields
+    ORDER BY name
+"#;
+const GET_CUSTOM_FIELD_SQL: &str = r#"
+    SELECT name, type, required, default_value, enum_values, indexed, created_at, updated_at
+    FROM custom_fields
+    WHERE name = $1
+"#;
+const CREATE_CUSTOM_FIELD_SQL: &str = r#"
+    INSERT INTO custom_fields (name, type, required, default_value, enum_values, indexed, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ON CONFLICT (name) DO NOTHING
+    RETURNING name, type, required, default_value, enum_values, indexed, created_at, updated_at
+"#;
+const LOCK_CUSTOM_FIELD_SQL: &str = r#"
+    SELECT name, type, required, default_value, enum_values, indexed, created_at, updated_at
+    FROM custom_fields
+    WHERE name = $1
+    FOR UPDATE
+"#;
+const COUNT_USERS_WITH_ATTRIBUTE_SQL: &str = "SELECT count(*) FROM users WHERE custom_data ? $1";
+const COUNT_USERS_WITHOUT_ATTRIBUTE_SQL: &str = "SELECT count(*) FROM users WHERE NOT custom_data ? $1";
+const UPDATE_CUSTOM_FIELD_SQL: &str = r#"
+    UPDATE custom_fields
+    SET type = $2, required = $3, default_value = $4, enum_values = $5, indexed = $6, updated_at = $7
+    WHERE name = $1
+    RETURNING name, type, required, default_value, enum_values, indexed, created_at, updated_at
+"#;
+const DELETE_CUSTOM_FIELD_SQL: &str = "DELETE FROM custom_fields WHERE name = $1 RETURNING name";
+const DELETE_USER_SQL: &str = r#"
+    DELETE FROM users
+    WHERE id = $1
+    RETURNING id, name, email, birth_date, custom_data, created_at, updated_at
+"#;
+
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait Repository: Send + Sync + 'static {
     async fn get_all(&self) -> RepositoryResultList<User>;
+    /// A page of every user, ordered by id.
+    async fn get_page(&self, page: &Page) -> RepositoryResultList<User>;
+    async fn count_users(&self, mode: CountMode) -> RepositoryResult<Total>;
+    /// Streams every user as rows arrive from the database, without buffering the table.
+    fn export_users(&self) -> BoxStream<'_, RepositoryResult<User>>;
     async fn get_user(&self, user_id: &Uuid) -> RepositoryResult<User>;
-    async fn get_user_by_email(&self, user_email: &String) -> RepositoryResult<User>;
+    /// Users with the given ids that exist, in no particular order.
+    async fn get_users(&self, user_ids: &[Uuid]) -> RepositoryResultList<User>;
+    async fn get_user_by_email(&self, user_email: &str) -> RepositoryResult<User>;
+    /// Users matching every filter; filters must be on indexed custom fields.
+    async fn get_users_by_custom_data(&self, filters: &[CustomDataFilter]) -> RepositoryResultList<User>;
+    /// Users matching a filter expression; custom data paths must be indexed custom fields.
+    async fn get_users_by_filter(&self, filter: &FilterExpression) -> RepositoryResultList<User>;
+    /// Users whose name or email match the search, even partially or with typos, best first.
+    async fn search_users(&self, query: &SearchQuery) -> RepositoryResultList<SearchHit>;
     async fn create_user(&self, user: &CreateUser) -> RepositoryResult<User>;
     async fn update_user(&self, user: &User) -> RepositoryResult<User>;
+    /// Creates the user with its own id or replaces the stored one; `true` when created.
+    async fn upsert_user(&self, user: &User) -> RepositoryResult<(User, bool)>;
+    /// Stores changes computed from the user as it was at `updated_at`, failing with 409 when it
+    /// changed since, so a patch never applies to a copy another request made stale.
+    async fn patch_user(
+        &self,
+        user_id: &Uuid,
+        changes: &PatchUser,
+        updated_at: Option<DateTime<Utc>>,
+    ) -> RepositoryResult<User>;
     async fn delete_user(&self, user_id: &Uuid) -> RepositoryResult<Uuid>;
+    /// Runs the operations in order within a transaction, returning a result per operation.
+    async fn bulk(&self, operations: &[BulkOperation], mode: BulkMode) -> RepositoryResultList<BulkItemResult>;
+    /// Stores an uploaded CSV as a pending import.
+    async fn create_import(&self, data: &[u8], total_rows: i64) -> RepositoryResult<ImportJob>;
+    async fn get_import(&self, import_id: &Uuid) -> RepositoryResult<ImportJob>;
+    /// Cancels an unfinished import; cancelling a finished one is a 409.
+    async fn cancel_import(&self, import_id: &Uuid) -> RepositoryResult<ImportJob>;
+    /// Locks the oldest unfinished import nobody holds a lease on, returning it with its file.
+    async fn claim_import(&self, lease: Duration) -> RepositoryResult<Option<(ImportJob, Vec<u8>)>>;
+    /// Creates the users of a batch and records the import progress in one transaction; a
+    /// cancelled import, or one resumed by another worker, is returned untouched.
+    async fn import_batch(&self, import_id: &Uuid, batch: &ImportBatch) -> RepositoryResult<ImportJob>;
+    async fn finish_import(
+        &self,
+        import_id: &Uuid,
+        status: ImportStatus,
+        error: Option<String>,
+    ) -> RepositoryResult<ImportJob>;
+    async fn list_custom_fields(&self) -> RepositoryResultList<CustomField>;
+    async fn get_custom_field(&self, name: &str) -> RepositoryResult<CustomField>;
+    /// Defines a custom field; a name already defined is a 409.
+    async fn create_custom_field(&self, field: &CustomField) -> RepositoryResult<CustomField>;
+    /// Replaces a definition; stored users are checked against it on their next write. A change
+    /// stored users may not satisfy, a new type, a narrower enum or becoming required without a
+    /// default, is a 409 while users have, or for the latter lack, the attribute.
+    async fn update_custom_field(&self, field: &CustomField) -> RepositoryResult<CustomField>;
+    /// Removes a definition, keeping the attribute on stored users.
+    async fn delete_custom_field(&self, name: &str) -> RepositoryResult<String>;
+}
+
+#[derive(FromRow)]
+struct CustomFieldRow {
+    name: String,
+    #[sqlx(rename = "type")]
+    field_type: String,
+    required: bool,
+    default_value: Option<serde_json::Value>,
+    enum_values: Option<sqlx::types::Json<Vec<serde_json::Value>>>,
+    indexed: bool,
+    created_at: DateTime<Utc>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl CustomFieldRow {
+    fn into_field(self) -> RepositoryResult<CustomField> {
+        let field_type = CustomFieldType::parse(&self.field_type).ok_or_else(|| {
+            tracing::error!("Custom field {} has an unknown type {}", self.name, self.field_type);
+            Error::new("Error on read custom field".to_string(), 502)
+        })?;
+        Ok(CustomField {
+            name: self.name,
+            field_type,
+            required: self.required,
+            default: self.default_value,
+            enum_values: self.enum_values.map(|values| values.0),
+            indexed: self.indexed,
+            created_at: Some(self.created_at),
+            updated_at: self.updated_at,
+        })
+    }
+}
+
+#[derive(FromRow)]
+struct ImportJobRow {
+    id: Uuid,
+    status: String,
+    total_rows: i64,
+    processed_rows: i64,
+    created_rows: i64,
+    failed_rows: i64,
+    errors: sqlx::types::Json<Vec<ImportError>>,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl ImportJobRow {
+    fn into_job(self) -> RepositoryResult<ImportJob> {
+        let status = ImportStatus::parse(&self.status).ok_or_else(|| {
+            tracing::error!("Import {} has an unknown status {}", self.id, self.status);
+            Error::new("Error on read import".to_string(), 502)
+        })?;
+        Ok(ImportJob {
+            id: self.id,
+            status,
+            total_rows: self.total_rows,
+            processed_rows: self.processed_rows,
+            created_rows: self.created_rows,
+            failed_rows: self.failed_rows,
+            errors: self.errors.0,
+            error: self.error,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        })
+    }
+}
+
+/// Custom field definitions last read, forgotten when this instance changes them.
+#[derive(Default)]
+struct CustomFieldsCache {
+    /// Bumped on every change, so a read started before it does not store stale definitions.
+    generation: u64,
+    fields: Option<(Instant, Vec<CustomField>)>,
 }
 
 pub struct PostgresRepository {
     pool: sqlx::PgPool,
+    metrics: Option<Metrics>,
+    email_rules: EmailRules,
+    custom_data_schema: CustomDataSchema,
+    custom_fields: RwLock<CustomFieldsCache>,
 }
 
 impl PostgresRepository {
     pub async fn from_env() -> sqlx::Result<Self> {
         let conn_str =
             std::env::var("DATABASE_URL").map_err(|e| sqlx::Error::Configuration(Box::new(e)))?;
+        let custom_data_schema =
+            CustomDataSchema::from_env().map_err(|e| sqlx::Error::Configuration(e.message.into()))?;
         let pool = sqlx::PgPool::connect(&conn_str).await?;
-        Ok(Self { pool })
+        let repository = Self {
+            pool,
+            metrics: None,
+            email_rules: EmailRules::from_env(),
+            custom_data_schema,
+            custom_fields: RwLock::default(),
+        };
+        repository.normalize_stored_emails().await?;
+        Ok(repository)
+    }
+
+    /// Stores existing emails in the form the provider rules give new writes and lookups, which
+    /// the migrations cannot do as the rules are configured per deployment. Like the migration
+    /// folding case, users whose emails would collide are reported and nothing is changed.
+    pub async fn normalize_stored_emails(&self) -> sqlx::Result<()> {
+        let domains = self.email_rules.provider_domains();
+        if domains.is_empty() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await?;
+        let rows: Vec<(Uuid, String)> = sqlx::query_as(PROVIDER_EMAILS_SQL)
+            .bind(domains.iter().map(|domain| domain.to_string()).collect::<Vec<_>>())
+            .fetch_all(&mut tx)
+            .await?;
+
+        let mut normalized: HashMap<String, Vec<(Uuid, String, String)>> = HashMap::new();
+        for (id, email) in rows {
+            // Invalid emails predate the validation and are left for their users to fix.
+            if let Ok(rewritten) = self.email_rules.normalize(&email) {
+                normalized.entry(rewritten.to_lowercase()).or_default().push((id, email, rewritten));
+            }
+        }
+        let collisions: Vec<String> = normalized
+            .values()
+            .filter(|users| users.len() > 1)
+            .map(|users| {
+                let ids: Vec<String> = users.iter().map(|(id, _, _)| id.to_string()).collect();
+                format!("{} users: {}", users.len(), ids.join(", "))
+            })
+            .collect();
+        if !collisions.is_empty() {
+            return Err(sqlx::Error::Configuration(
+                format!("Users with emails colliding under the provider rules:\n{}", collisions.join("\n")).into(),
+            ));
+        }
+
+        let mut updated = 0;
+        for (id, email, rewritten) in normalized.into_values().flatten() {
+            if email != rewritten {
+                sqlx::query(UPDATE_EMAIL_SQL).bind(id).bind(rewritten).execute(&mut tx).await?;
+                updated += 1;
+            }
+        }
+        tx.commit().await?;
+        if updated > 0 {
+            tracing::info!("Normalized {} stored emails with the provider rules", updated);
+        }
+        Ok(())
+    }
+
+    pub fn with_metrics(mut self, metrics: &Metrics) -> Self {
+        metrics.register_pool(self.pool.clone());
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Runs a repository call inside a client span carrying its SQL statement, recording
+    /// metrics when enabled.
+    async fn observe<T, F>(&self, method: &str, statement: &str, call: F) -> RepositoryResult<T>
+    where
+        F: Future<Output = RepositoryResult<T>>,
+    {
+        let call = call.instrument(repository_span(method, statement));
+        match &self.metrics {
+            Some(metrics) => metrics.observe_repository(method, call).await,
+            None => call.await,
+        }
+    }
+
+    /// Like [`observe`](Self::observe) for a stream: the span and metrics last until it ends or
+    /// is dropped.
+    fn observe_stream<'a, T: Send + 'a>(
+        &self,
+        method: &str,
+        statement: &str,
+        stream: BoxStream<'a, RepositoryResult<T>>,
+    ) -> BoxStream<'a, RepositoryResult<T>> {
+        let span = repository_span(method, statement);
+        let stream = tracing_futures::Instrument::instrument(stream, span).boxed();
+        match &self.metrics {
+            Some(metrics) => metrics.observe_repository_stream(method, stream),
+            None => stream,
+        }
     }
 }
 
 #[async_trait]
 impl Repository for PostgresRepository {
     async fn get_all(&self) -> RepositoryResultList<User> {
-        let users = sqlx::query_as::<_, User>(
-            "SELECT * FROM users",
-        ).fetch_all(&self.pool)
-        .await;
+        self.observe("get_all", GET_ALL_SQL, async {
+            let users = sqlx::query_as::<_, User>(GET_ALL_SQL)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error on get all users, error: {:?}", RedactedDbError(&e));
+                    Error::new("Error on get all users".to_string(), 502)
+                })?;
 
-        tracing::info!("Repository returning {} users", users.as_ref().unwrap().len());
+            tracing::info!("Repository returning {} users", users.len());
+            Ok(users)
+        })
+        .await
+    }
 
-        users.map_err(|e| {
-            tracing::error!("Error on get all users, error: {:?}", e);
-            Error::new("Error on get all users".to_string(), 502)
+    async fn get_page(&self, page: &Page) -> RepositoryResultList<User> {
+        self.observe("get_page", GET_PAGE_SQL, async {
+            let users = sqlx::query_as::<_, User>(GET_PAGE_SQL)
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error on get page of users, error: {:?}", RedactedDbError(&e));
+                    Error::new("Error on get all users".to_string(), 502)
+                })?;
+
+            tracing::info!("Repository returning {} users from offset {}", users.len(), page.offset);
+            Ok(users)
         })
+        .await
+    }
+
+    async fn count_users(&self, mode: CountMode) -> RepositoryResult<Total> {
+        let count_error = |e: sqlx::Error| {
+            tracing::error!("Error on count users, error: {:?}", RedactedDbError(&e));
+            Error::new("Error on count users".to_string(), 502)
+        };
+        if mode == CountMode::Estimated {
+            let estimate: i64 = self
+                .observe("estimate_users", ESTIMATE_USERS_SQL, async {
+                    sqlx::query_scalar(ESTIMATE_USERS_SQL).fetch_one(&self.pool).await.map_err(count_error)
+                })
+                .await?;
+            if estimate >= 0 {
+                return Ok(Total { count: estimate, estimated: true });
+            }
+            tracing::info!("Users table has no statistics yet, counting exactly");
+        }
+        let count: i64 = self
+            .observe("count_users", COUNT_USERS_SQL, async {
+                sqlx::query_scalar(COUNT_USERS_SQL).fetch_one(&self.pool).await.map_err(count_error)
+            })
+            .await?;
+        Ok(Total { count, estimated: false })
+    }
+
+    fn export_users(&self) -> BoxStream<'_, RepositoryResult<User>> {
+        tracing::info!("Repository exporting users");
+        let users = sqlx::query_as::<_, User>(EXPORT_USERS_SQL)
+            .fetch(&self.pool)
+            .map(|result| {
+                result.map_err(|e| {
+                    tracing::error!("Error on export users, error: {:?}", RedactedDbError(&e));
+                    Error::new("Error on export users".to_string(), 502)
+                })
+            })
+            .boxed();
+        self.observe_stream("export_users", EXPORT_USERS_SQL, users)
     }
 
     async fn get_user(&self, user_id: &uuid::Uuid) -> RepositoryResult<User> {
-        let result = sqlx::query_as::<_, User>(
-            "SELECT id, name, email, birth_date, custom_data, created_at, updated_at FROM users WHERE id = $1",
-        )
-        .bind(user_id)
-        .fetch_one(&self.pool)
-        .await;
+        self.observe("get_user", GET_USER_SQL, async {
+            let user = sqlx::query_as::<_, User>(GET_USER_SQL)
+                .bind(user_id)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| match e {
+                    sqlx::Error::RowNotFound => {
+                        tracing::warn!("User with id {} not found", user_id);
+                        Error::new("Invalid Uuid".to_string(), 404)
+                    }
+                    e => {
+                        tracing::error!("Error on get user {}, error: {:?}", user_id, RedactedDbError(&e));
+                        Error::new("Error on get user".to_string(), 502)
+                    }
+                })?;
+
+            tracing::info!("Repository returning {:?}", user);
+            Ok(user)
+        })
+        .await
+    }
 
-        tracing::info!("Repository returning {:?}", result.as_ref().to_owned().unwrap());
+    async fn get_users(&self, user_ids: &[Uuid]) -> RepositoryResultList<User> {
+        self.observe("get_users", GET_USERS_SQL, async {
+            let users = sqlx::query_as::<_, User>(GET_USERS_SQL)
+                .bind(user_ids)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error on get users by ids, error: {:?}", RedactedDbError(&e));
+                    Error::new("Error on get users".to_string(), 502)
+                })?;
 
-        result.map_err(|e| {
-            tracing::error!("{:?}", e);
-            Error::new("Invalid Uuid".to_string(), 404)
+            tracing::info!("Repository returning {} of {} users", users.len(), user_ids.len());
+            Ok(users)
         })
+        .await
     }
 
-    async fn get_user_by_email(&self, user_email: &String) -> RepositoryResult<User> {
-        let result = sqlx::query_as::<_, User>(
-            "SELECT id, name, email, birth_date, custom_data, created_at, updated_at FROM users WHERE email = $1",
-        )
-        .bind(user_email)
-        .fetch_one(&self.pool)
-        .await;
+    async fn get_user_by_email(&self, user_email: &str) -> RepositoryResult<User> {
+        self.observe("get_user_by_email", GET_USER_BY_EMAIL_SQL, async {
+            let email = self.email_rules.normalize(user_email)?;
+            let result = sqlx::query_as::<_, User>(GET_USER_BY_EMAIL_SQL)
+                .bind(&email)
+                .fetch_one(&self.pool)
+                .await;
 
-        result.map_err(|e| {
-            tracing::error!("Error on get user by email {}. Error: {:?}", user_email, e);
-            Error::new("Error on get user by email".to_string(), 400)
+            result.map_err(|e| match e {
+                sqlx::Error::RowNotFound => {
+                    tracing::warn!("User with email {} not found", RedactedEmail(&email));
+                    Error::new("This user does not exist".to_string(), 404)
+                }
+                e => {
+                    tracing::error!(
+                        "Error on get user by email {}. Error: {:?}",
+                        RedactedEmail(&email),
+                        RedactedDbError(&e)
+                    );
+                    Error::new("Error on get user by email".to_string(), 502)
+                }
+            })
         })
+        .await
     }
 
-    async fn create_user(&self, user: &CreateUser) -> RepositoryResult<User> {
-        if let Ok(_old_user) = self.get_user_by_email(&user.email).await {
-            tracing::warn!("User with email {} already exists", user.email);
-            return Result::Err(Error::new("This user already exists".to_string(), 422));
-        }
+    async fn get_users_by_custom_data(&self, filters: &[CustomDataFilter]) -> RepositoryResultList<User> {
+        let fields = self.custom_fields().await?;
+        let (sql, binds) = custom_data_conditions(&fields, filters)?;
+        self.observe("get_users_by_custom_data", &sql, self.filtered_users(&sql, binds)).await
+    }
 
-        let result = sqlx::query_as::<_, User>(
-            r#"
-            INSERT INTO users (id, name, email, birth_date, custom_data, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            RETURNING id, name, email, birth_date, custom_data, created_at, updated_at
-            "#,
-        )
-        .bind(Uuid::new_v4())
-        .bind(&user.name)
-        .bind(&user.email)
-        .bind(&user.birth_date)
-        .bind(&user.custom_data)
-        .bind(Utc::now())
-        .fetch_one(&self.pool)
-        .await;
+    async fn get_users_by_filter(&self, filter: &FilterExpression) -> RepositoryResultList<User> {
+        let fields = self.custom_fields().await?;
+        let (sql, binds) = filter_expression_query(&fields, &self.email_rules, filter)?;
+        self.observe("get_users_by_filter", &sql, self.filtered_users(&sql, binds)).await
+    }
+
+    async fn search_users(&self, query: &SearchQuery) -> RepositoryResultList<SearchHit> {
+        self.observe("search_users", SEARCH_USERS_SQL, async {
+            let rows = sqlx::query(SEARCH_USERS_SQL)
+                .bind(&query.q)
+                .bind(query.prefix_tsquery())
+                .bind(query.limit())
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error on search users, error: {:?}", RedactedDbError(&e));
+                    Error::new("Error on search users".to_string(), 502)
+                })?;
 
-        tracing::info!("User with email {} was created", user.email);
+            let hits = rows
+                .iter()
+                .map(|row| {
+                    let user = User::from_row(row)?;
+                    let rank: f32 = row.try_get("rank")?;
+                    Ok(SearchHit::new(user, rank, query))
+                })
+                .collect::<Result<Vec<_>, sqlx::Error>>()
+                .map_err(|e| {
+                    tracing::error!("Error on read searched users, error: {:?}", RedactedDbError(&e));
+                    Error::new("Error on search users".to_string(), 502)
+                })?;
+            tracing::info!("Search returning {} users", hits.len());
+            Ok(hits)
+        })
+        .await
+    }
+
+    async fn create_user(&self, user: &CreateUser) -> RepositoryResult<User> {
+        self.observe("create_user", CREATE_USER_SQL, async {
+            let email = self.email_rules.normalize(&user.email)?;
+            let custom_data = self.checked_custom_data(&self.custom_fields().await?, &user.custom_data)?;
+            let user = sqlx::query_as::<_, User>(CREATE_USER_SQL)
+                .bind(Uuid::new_v4())
+                .bind(&user.name)
+                .bind(&email)
+                .bind(user.birth_date)
+                .bind(&custom_data)
+                .bind(Utc::now())
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| map_write_error(e, "Error on create user"))?;
 
-        result.map_err(|e| {
-            tracing::error!("{:?}", e);
-            Error::new("This user already exists".to_string(), 400)
+            tracing::info!("User with email {} was created", RedactedEmail(&user.email));
+            Ok(user)
         })
+        .await
     }
 
     async fn update_user(&self, user: &User) -> RepositoryResult<User> {
-        if let Ok(_old_user) = self.get_user(&user.id).await {
-            if let Ok(database_user) = self.get_user_by_email(&user.email).await {
-                if database_user.email != user.email {
-                    tracing::warn!("User with email {} already exists", user.email);
-                    return Result::Err(Error::new("This user email already exists".to_string(), 422));
+        self.observe("update_user", UPDATE_USER_SQL, async {
+            let email = self.email_rules.normalize(&user.email)?;
+            let custom_data = self.checked_custom_data(&self.custom_fields().await?, &user.custom_data)?;
+            update_in(&self.pool, user, &email, &custom_data).await
+        })
+        .await
+    }
+
+    async fn upsert_user(&self, user: &User) -> RepositoryResult<(User, bool)> {
+        self.observe("upsert_user", UPSERT_USER_SQL, async {
+            let email = self.email_rules.normalize(&user.email)?;
+            let custom_data = self.checked_custom_data(&self.custom_fields().await?, &user.custom_data)?;
+            let row = sqlx::query(UPSERT_USER_SQL)
+                .bind(user.id)
+                .bind(&user.name)
+                .bind(&email)
+                .bind(user.birth_date)
+                .bind(&custom_data)
+                .bind(Utc::now())
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| map_write_error(e, "Error on upsert user"))?;
+
+            let created: bool = row.try_get("created").map_err(|e| map_write_error(e, "Error on upsert user"))?;
+            let user = User::from_row(&row).map_err(|e| map_write_error(e, "Error on upsert user"))?;
+            if created {
+                tracing::info!("User with id {} was created", user.id);
+            } else {
+                tracing::info!("User with id {} was replaced", user.id);
+            }
+            Ok((user, created))
+        })
+        .await
+    }
+
+    async fn patch_user(
+        &self,
+        user_id: &Uuid,
+        changes: &PatchUser,
+        updated_at: Option<DateTime<Utc>>,
+    ) -> RepositoryResult<User> {
+        self.observe("patch_user", PATCH_USER_SQL, async {
+            let email = match &changes.email {
+                Some(email) => Some(self.email_rules.normalize(email)?),
+                None => None,
+            };
+            let custom_data = match &changes.custom_data {
+                Some(custom_data) => Some(self.checked_custom_data(&self.custom_fields().await?, custom_data)?),
+                None => None,
+            };
+            let result = sqlx::query_as::<_, User>(PATCH_USER_SQL)
+                .bind(email)
+                .bind(&changes.name)
+                .bind(changes.birth_date)
+                .bind(custom_data)
+                .bind(Utc::now())
+                .bind(user_id)
+                .bind(updated_at)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| map_write_error(e, "Error on patch user"))?;
+
+            if let Some(user) = result {
+                tracing::info!("User with id {} was patched", user.id);
+                return Ok(user);
+            }
+            let exists: bool = sqlx::query_scalar(USER_EXISTS_SQL)
+                .bind(user_id)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error on patch user: {:?}", RedactedDbError(&e));
+                    Error::new("Error on patch user".to_string(), 502)
+                })?;
+            if exists {
+                tracing::info!("User with id {} changed since it was read, patch refused", user_id);
+                Err(PatchUser::stale())
+            } else {
+                tracing::error!("User with id {} not found", user_id);
+                Err(Error::new("This user does not exist".to_string(), 404))
+            }
+        })
+        .await
+    }
+
+    async fn delete_user(&self, user_id: &Uuid) -> RepositoryResult<Uuid> {
+        self.observe("delete_user", DELETE_USER_SQL, async {
+            let result = sqlx::query_as::<_, User>(DELETE_USER_SQL)
+                .bind(user_id)
+                .fetch_one(&self.pool)
+                .await;
+
+            result.map(|u| u.id).map_err(|e| match e {
+                sqlx::Error::RowNotFound => {
+                    tracing::warn!("User with id {} not found", user_id);
+                    Error::new("This user does not exist".to_string(), 404)
                 }
+                e => {
+                    tracing::error!("Error on remove user {}, error: {:?}", user_id, RedactedDbError(&e));
+                    Error::new("Error on remove user".to_string(), 502)
+                }
+            })
+        })
+        .await
+    }
+
+    async fn bulk(&self, operations: &[BulkOperation], mode: BulkMode) -> RepositoryResultList<BulkItemResult> {
+        self.observe("bulk", CREATE_USERS_SQL, async {
+            let fields = self.custom_fields().await?;
+            let mut tx = self.pool.begin().await.map_err(|e| map_write_error(e, "Error on bulk"))?;
+            let results = self.bulk_in(&mut tx, &fields, operations, mode).await?;
+            tx.commit().await.map_err(|e| map_write_error(e, "Error on bulk"))?;
+            tracing::info!("Bulk of {} operations was applied", operations.len());
+            Ok(results)
+        })
+        .await
+    }
+
+    async fn create_import(&self, data: &[u8], total_rows: i64) -> RepositoryResult<ImportJob> {
+        self.observe("create_import", CREATE_IMPORT_SQL, async {
+            let job = sqlx::query_as::<_, ImportJobRow>(CREATE_IMPORT_SQL)
+                .bind(Uuid::new_v4())
+                .bind(data)
+                .bind(total_rows)
+                .bind(Utc::now())
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| map_write_error(e, "Error on create import"))?
+                .into_job()?;
+
+            tracing::info!("Import {} of {} rows was created", job.id, job.total_rows);
+            Ok(job)
+        })
+        .await
+    }
+
+    async fn get_import(&self, import_id: &Uuid) -> RepositoryResult<ImportJob> {
+        self.observe("get_import", GET_IMPORT_SQL, async {
+            sqlx::query_as::<_, ImportJobRow>(GET_IMPORT_SQL)
+                .bind(import_id)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| map_read_error(e, "Error on get import"))?
+                .ok_or_else(|| Error::new("This import does not exist".to_string(), 404))?
+                .into_job()
+        })
+        .await
+    }
+
+    async fn cancel_import(&self, import_id: &Uuid) -> RepositoryResult<ImportJob> {
+        let cancelled = self
+            .observe("cancel_import", CANCEL_IMPORT_SQL, async {
+                sqlx::query_as::<_, ImportJobRow>(CANCEL_IMPORT_SQL)
+                    .bind(import_id)
+                    .bind(Utc::now())
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| map_write_error(e, "Error on cancel import"))
+            })
+            .await?;
+
+        match cancelled {
+            Some(job) => {
+                tracing::info!("Import {} was cancelled", import_id);
+                job.into_job()
+            }
+            None => {
+                let job = self.get_import(import_id).await?;
+                Err(Error::new(format!("This import is already {}", job.status.as_str()), 409))
             }
+        }
+    }
 
-            let result = sqlx::query_as::<_, User>(
-                r#"
-                UPDATE users
-                SET custom_data = $1, updated_at = $2, name = $3, email = $4
-                WHERE id = $5
-                RETURNING id, name, email, birth_date, custom_data, created_at, updated_at
-                "#,
-            )
-            .bind(&user.custom_data)
-            .bind(Utc::now())
-            .bind(&user.name)
-            .bind(&user.email)
-            .bind(&user.id)
-            .fetch_one(&self.pool)
-            .await;
-
-            tracing::info!("User with email {} was updated", user.email);
-
-            result.map_err(|e| {
-                tracing::error!("{:?}", e);
-                Error::new("Error on update user".to_string(), 502)
+    async fn claim_import(&self, lease: Duration) -> RepositoryResult<Option<(ImportJob, Vec<u8>)>> {
+        self.observe("claim_import", CLAIM_IMPORT_SQL, async {
+            let now = Utc::now();
+            let row = sqlx::query(CLAIM_IMPORT_SQL)
+                .bind(lease_end(now, lease))
+                .bind(now)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| map_write_error(e, "Error on claim import"))?;
+
+            match row {
+                Some(row) => {
+                    let data: Vec<u8> = row.try_get("data").map_err(|e| map_write_error(e, "Error on claim import"))?;
+                    let job = ImportJobRow::from_row(&row)
+                        .map_err(|e| map_write_error(e, "Error on claim import"))?
+                        .into_job()?;
+                    Ok(Some((job, data)))
+                }
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn import_batch(&self, import_id: &Uuid, batch: &ImportBatch) -> RepositoryResult<ImportJob> {
+        self.observe("import_batch", UPDATE_IMPORT_PROGRESS_SQL, async {
+            let fields = self.custom_fields().await?;
+            let mut tx = self.pool.begin().await.map_err(|e| map_write_error(e, "Error on import batch"))?;
+            let job = sqlx::query_as::<_, ImportJobRow>(LOCK_IMPORT_SQL)
+                .bind(import_id)
+                .fetch_optional(&mut tx)
+                .await
+                .map_err(|e| map_write_error(e, "Error on import batch"))?
+                .ok_or_else(|| Error::new("This import does not exist".to_string(), 404))?
+                .into_job()?;
+            if job.status != ImportStatus::Running {
+                return Ok(job);
+            }
+            if job.processed_rows != batch.from_row {
+                tracing::info!(
+                    "Import {} is at row {}, dropping a stale batch from row {}",
+                    job.id,
+                    job.processed_rows,
+                    batch.from_row
+                );
+                return Ok(job);
+            }
+
+            let operations: Vec<BulkOperation> = batch
+                .users
+                .iter()
+                .map(|(_, user)| BulkOperation::Create { user: user.clone() })
+                .collect();
+            let results = self.bulk_in(&mut tx, &fields, &operations, BulkMode::BestEffort).await?;
+
+            let mut failures = batch.failures.clone();
+            let mut created_rows = 0i64;
+            for ((line, _), result) in batch.users.iter().zip(results) {
+                match result.error {
+                    Some(err) => failures.push(ImportError::new(*line, err)),
+                    None => created_rows += 1,
+                }
+            }
+            let failed_rows = failures.len() as i64;
+            failures.sort_by_key(|failure| failure.line);
+            failures.truncate(MAX_ERROR_SAMPLES.saturating_sub(job.errors.len()));
+
+            let now = Utc::now();
+            let job = sqlx::query_as::<_, ImportJobRow>(UPDATE_IMPORT_PROGRESS_SQL)
+                .bind(import_id)
+                .bind(batch.processed_rows)
+                .bind(created_rows)
+                .bind(failed_rows)
+                .bind(sqlx::types::Json(&failures))
+                .bind(lease_end(now, batch.lease))
+                .bind(now)
+                .fetch_one(&mut tx)
+                .await
+                .map_err(|e| map_write_error(e, "Error on import batch"))?
+                .into_job()?;
+            tx.commit().await.map_err(|e| map_write_error(e, "Error on import batch"))?;
+
+            tracing::info!("Import {} processed {} of {} rows", job.id, job.processed_rows, job.total_rows);
+            Ok(job)
+        })
+        .await
+    }
+
+    async fn finish_import(
+        &self,
+        import_id: &Uuid,
+        status: ImportStatus,
+        error: Option<String>,
+    ) -> RepositoryResult<ImportJob> {
+        let finished = self
+            .observe("finish_import", FINISH_IMPORT_SQL, async {
+                sqlx::query_as::<_, ImportJobRow>(FINISH_IMPORT_SQL)
+                    .bind(import_id)
+                    .bind(status.as_str())
+                    .bind(&error)
+                    .bind(Utc::now())
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| map_write_error(e, "Error on finish import"))
             })
-        } else {
+            .await?;
+
+        match finished {
+            Some(job) => job.into_job(),
+            // Cancelled while its last batch was processed.
+            None => self.get_import(import_id).await,
+        }
+    }
+
+    async fn list_custom_fields(&self) -> RepositoryResultList<CustomField> {
+        self.observe("list_custom_fields", LIST_CUSTOM_FIELDS_SQL, async {
+            sqlx::query_as::<_, CustomFieldRow>(LIST_CUSTOM_FIELDS_SQL)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| map_read_error(e, "Error on list custom fields"))?
+                .into_iter()
+                .map(CustomFieldRow::into_field)
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_custom_field(&self, name: &str) -> RepositoryResult<CustomField> {
+        self.observe("get_custom_field", GET_CUSTOM_FIELD_SQL, async {
+            sqlx::query_as::<_, CustomFieldRow>(GET_CUSTOM_FIELD_SQL)
+                .bind(name)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| map_read_error(e, "Error on get custom field"))?
+                .ok_or_else(|| Error::new("This custom field does not exist".to_string(), 404))?
+                .into_field()
+        })
+        .await
+    }
+
+    async fn create_custom_field(&self, field: &CustomField) -> RepositoryResult<CustomField> {
+        self.observe("create_custom_field", CREATE_CUSTOM_FIELD_SQL, async {
+            let field = sqlx::query_as::<_, CustomFieldRow>(CREATE_CUSTOM_FIELD_SQL)
+                .bind(&field.name)
+                .bind(field.field_type.as_str())
+                .bind(field.required)
+                .bind(&field.default)
+                .bind(field.enum_values.as_ref().map(sqlx::types::Json))
+                .bind(field.indexed)
+                .bind(Utc::now())
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| map_write_error(e, "Error on create custom field"))?
+                .ok_or_else(|| {
+                    Error::new("A custom field with this name already exists".to_string(), 409)
+                        .with_field("name".to_string())
+                })?
+                .into_field()?;
+
+            self.forget_custom_fields();
+            tracing::info!("Custom field {} was created", field.name);
+            Ok(field)
+        })
+        .await
+    }
+
+    async fn update_custom_field(&self, field: &CustomField) -> RepositoryResult<CustomField> {
+        self.observe("update_custom_field", UPDATE_CUSTOM_FIELD_SQL, async {
+            let write_error = |e: sqlx::Error| map_write_error(e, "Error on update custom field");
+            let mut tx = self.pool.begin().await.map_err(write_error)?;
+            let previous = sqlx::query_as::<_, CustomFieldRow>(LOCK_CUSTOM_FIELD_SQL)
+                .bind(&field.name)
+                .fetch_optional(&mut tx)
+                .await
+                .map_err(write_error)?
+                .ok_or_else(|| Error::new("This custom field does not exist".to_string(), 404))?
+                .into_field()?;
+
+            if let Some(property) = field.restricted_from(&previous) {
+                let users: i64 = sqlx::query_scalar(COUNT_USERS_WITH_ATTRIBUTE_SQL)
+                    .bind(&field.name)
+                    .fetch_one(&mut tx)
+                    .await
+                    .map_err(write_error)?;
+                if users > 0 {
+                    let message = format!(
+                        "The {} of custom field {} cannot change while {} users have it",
+                        property, field.name, users
+                    );
+                    return Err(Error::new(message, 409).with_field(property.to_string()));
+                }
+            }
+            if field.requires_missing(&previous) {
+                let users: i64 = sqlx::query_scalar(COUNT_USERS_WITHOUT_ATTRIBUTE_SQL)
+                    .bind(&field.name)
+                    .fetch_one(&mut tx)
+                    .await
+                    .map_err(write_error)?;
+                if users > 0 {
+                    let message = format!(
+                        "Custom field {} cannot be required without a default while {} users lack it",
+                        field.name, users
+                    );
+                    return Err(Error::new(message, 409).with_field("required".to_string()));
+                }
+            }
+
+            let field = sqlx::query_as::<_, CustomFieldRow>(UPDATE_CUSTOM_FIELD_SQL)
+                .bind(&field.name)
+                .bind(field.field_type.as_str())
+                .bind(field.required)
+                .bind(&field.default)
+                .bind(field.enum_values.as_ref().map(sqlx::types::Json))
+                .bind(field.indexed)
+                .bind(Utc::now())
+                .fetch_one(&mut tx)
+                .await
+                .map_err(write_error)?
+                .into_field()?;
+            tx.commit().await.map_err(write_error)?;
+
+            self.forget_custom_fields();
+            tracing::info!("Custom field {} was updated", field.name);
+            Ok(field)
+        })
+        .await
+    }
+
+    async fn delete_custom_field(&self, name: &str) -> RepositoryResult<String> {
+        self.observe("delete_custom_field", DELETE_CUSTOM_FIELD_SQL, async {
+            let deleted: Option<String> = sqlx::query_scalar(DELETE_CUSTOM_FIELD_SQL)
+                .bind(name)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| map_write_error(e, "Error on delete custom field"))?;
+
+            let name = deleted.ok_or_else(|| Error::new("This custom field does not exist".to_string(), 404))?;
+            self.forget_custom_fields();
+            tracing::info!("Custom field {} was deleted", name);
+            Ok(name)
+        })
+        .await
+    }
+}
+
+impl PostgresRepository {
+    /// Custom field definitions, read again once [`CUSTOM_FIELDS_TTL`] passed or after a change.
+    async fn custom_fields(&self) -> RepositoryResultList<CustomField> {
+        let generation = {
+            let cache = self.custom_fields.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match &cache.fields {
+                Some((read_at, fields)) if read_at.elapsed() < CUSTOM_FIELDS_TTL => return Ok(fields.clone()),
+                _ => cache.generation,
+            }
+        };
+        let read_at = Instant::now();
+        let fields = self.list_custom_fields().await?;
+        let mut cache = self.custom_fields.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if cache.generation == generation {
+            cache.fields = Some((read_at, fields.clone()));
+        }
+        Ok(fields)
+    }
+
+    fn forget_custom_fields(&self) {
+        let mut cache = self.custom_fields.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        cache.generation += 1;
+        cache.fields = None;
+    }
+
+    /// Applies operations within `tx`; `fields` are read beforehand, as holding the transaction
+    /// while waiting for another pool connection can exhaust the pool.
+    async fn bulk_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        fields: &[CustomField],
+        operations: &[BulkOperation],
+        mode: BulkMode,
+    ) -> RepositoryResultList<BulkItemResult> {
+        let mut results = Vec::with_capacity(operations.len());
+
+        let mut index = 0;
+        while index < operations.len() {
+            let creates: Vec<&CreateUser> = operations[index..]
+                .iter()
+                .take(BULK_INSERT_ROWS)
+                .map_while(|operation| match operation {
+                    BulkOperation::Create { user } => Some(user),
+                    _ => None,
+                })
+                .collect();
+            if creates.len() > 1 {
+                if let Ok(users) = self.insert_users(tx, &creates, fields).await {
+                    results.extend(users.into_iter().map(|user| BulkItemResult::user(201, user)));
+                    index += creates.len();
+                    continue;
+                }
+            }
+
+            // A failed multi-row insert is retried one row at a time to find the failing ones.
+            let run = creates.len().max(1);
+            for (offset, operation) in operations[index..index + run].iter().enumerate() {
+                match self.bulk_operation(tx, operation, fields).await {
+                    Ok(result) => results.push(result),
+                    Err(err) if mode == BulkMode::BestEffort => results.push(BulkItemResult::failed(err)),
+                    Err(err) => return Err(operation_error(index + offset, err)),
+                }
+            }
+            index += run;
+        }
+        Ok(results)
+    }
+
+    async fn bulk_operation(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        operation: &BulkOperation,
+        fields: &[CustomField],
+    ) -> RepositoryResult<BulkItemResult> {
+        match operation {
+            BulkOperation::Create { user } => {
+                let mut users = self.insert_users(tx, &[user], fields).await?;
+                Ok(BulkItemResult::user(201, users.remove(0)))
+            }
+            BulkOperation::Update { user } => {
+                let email = self.email_rules.normalize(&user.email)?;
+                let custom_data = self.checked_custom_data(fields, &user.custom_data)?;
+                let mut savepoint = begin_savepoint(tx).await?;
+                let result = update_in(&mut savepoint, user, &email, &custom_data).await;
+                end_savepoint(savepoint, result).await.map(|user| BulkItemResult::user(200, user))
+            }
+            BulkOperation::Delete { id } => {
+                let mut savepoint = begin_savepoint(tx).await?;
+                let result = sqlx::query_as::<_, User>(DELETE_USER_SQL)
+                    .bind(id)
+                    .fetch_optional(&mut savepoint)
+                    .await
+                    .map_err(|e| map_write_error(e, "Error on remove user"))
+                    .and_then(|user| {
+                        user.map(|user| BulkItemResult::deleted(user.id))
+                            .ok_or_else(|| Error::new("This user does not exist".to_string(), 404))
+                    });
+                end_savepoint(savepoint, result).await
+            }
+        }
+    }
+
+    /// Inserts users with a single statement inside a savepoint of `tx`, in the given order.
+    async fn insert_users(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        users: &[&CreateUser],
+        fields: &[CustomField],
+    ) -> RepositoryResultList<User> {
+        let emails = users
+            .iter()
+            .map(|user| self.email_rules.normalize(&user.email))
+            .collect::<Result<Vec<_>, _>>()?;
+        let custom_data = users
+            .iter()
+            .map(|user| self.checked_custom_data(fields, &user.custom_data))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ids: Vec<Uuid> = users.iter().map(|_| Uuid::new_v4()).collect();
+        let now = Utc::now();
+
+        let sql = create_users_sql(users.len());
+        let mut query = sqlx::query_as::<_, User>(&sql);
+        for (((id, user), email), custom_data) in ids.iter().zip(users).zip(&emails).zip(&custom_data) {
+            query = query
+                .bind(id)
+                .bind(&user.name)
+                .bind(email)
+                .bind(user.birth_date)
+                .bind(custom_data)
+                .bind(now);
+        }
+
+        let mut savepoint = begin_savepoint(tx).await?;
+        let result = query
+            .fetch_all(&mut savepoint)
+            .await
+            .map_err(|e| map_write_error(e, "Error on create users"));
+        let mut created: HashMap<Uuid, User> = end_savepoint(savepoint, result)
+            .await?
+            .into_iter()
+            .map(|user| (user.id, user))
+            .collect();
+
+        tracing::info!("{} users were created", created.len());
+        Ok(ids.iter().filter_map(|id| created.remove(id)).collect())
+    }
+
+    /// Runs a filtered `users` query built with its bound values.
+    async fn filtered_users(&self, sql: &str, binds: Vec<FilterBind>) -> RepositoryResultList<User> {
+        let mut query = sqlx::query_as::<_, User>(sql);
+        for bind in binds {
+            query = match bind {
+                FilterBind::Json(value) => query.bind(value),
+                FilterBind::Text(text) => query.bind(text),
+            };
+        }
+        let users = query.fetch_all(&self.pool).await.map_err(|e| {
+            tracing::error!("Error on get filtered users, error: {:?}", RedactedDbError(&e));
+            Error::new("Error on get users".to_string(), 502)
+        })?;
+
+        tracing::info!("Repository returning {} filtered users", users.len());
+        Ok(users)
+    }
+
+    /// Checks `custom_data` against the custom field definitions and the schema, returning it
+    /// with the defaults of missing fields.
+    fn checked_custom_data(&self, fields: &[CustomField], custom_data: &CustomData) -> RepositoryResult<CustomData> {
+        let custom_data = custom_field::apply(fields, custom_data)?;
+        self.custom_data_schema.validate(&custom_data)?;
+        Ok(custom_data)
+    }
+}
+
+async fn update_in<'e, E>(executor: E, user: &User, email: &str, custom_data: &CustomData) -> RepositoryResult<User>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    let result = sqlx::query_as::<_, User>(UPDATE_USER_SQL)
+        .bind(custom_data)
+        .bind(Utc::now())
+        .bind(&user.name)
+        .bind(email)
+        .bind(user.birth_date)
+        .bind(user.id)
+        .fetch_optional(executor)
+        .await
+        .map_err(|e| map_write_error(e, "Error on update user"))?;
+
+    match result {
+        Some(user) => {
+            tracing::info!("User with email {} was updated", RedactedEmail(&user.email));
+            Ok(user)
+        }
+        None => {
             tracing::error!("User with id {} not found", user.id);
-            Err(Error::new("This user does not exist".to_string(), 400))
+            Err(Error::new("This user does not exist".to_string(), 404))
         }
     }
+}
 
-    async fn delete_user(&self, user_id: &Uuid) -> RepositoryResult<Uuid> {
-        let result = sqlx::query_as::<_, User>(
-            r#"
-            DELETE FROM users
-            WHERE id = $1
-            RETURNING id, name, email, birth_date, custom_data, created_at, updated_at
-            "#,
-        )
-        .bind(user_id)
-        .fetch_one(&self.pool)
-        .await;
+/// Value bound to a filter condition.
+#[derive(Debug, PartialEq)]
+enum FilterBind {
+    Json(serde_json::Value),
+    Text(String),
+}
+
+/// Appends a value to bind and returns its placeholder.
+fn push_bind(binds: &mut Vec<FilterBind>, bind: FilterBind) -> String {
+    binds.push(bind);
+    format!("${}", binds.len())
+}
+
+/// Translates filters into conditions on the `custom_data` JSONB column, every value being bound.
+fn custom_data_conditions(
+    fields: &[CustomField],
+    filters: &[CustomDataFilter],
+) -> RepositoryResult<(String, Vec<FilterBind>)> {
+    let mut conditions = Vec::with_capacity(filters.len());
+    let mut binds = Vec::new();
+    for filter in filters {
+        conditions.push(custom_data_condition(fields, filter, &mut binds)?);
+    }
+
+    let conditions = if conditions.is_empty() { "true".to_string() } else { conditions.join(" AND ") };
+    Ok((format!("{} {}", GET_FILTERED_USERS_SQL, conditions), binds))
+}
+
+/// Finds the definition of a filtered `custom_data` path, which must be indexed.
+fn filtered_custom_field<'a>(fields: &'a [CustomField], path: &str) -> RepositoryResult<&'a CustomField> {
+    let field = fields.iter().find(|field| field.name == path).ok_or_else(|| {
+        Error::new(format!("Unknown custom data path {}{}", FILTER_PREFIX, path), 400)
+            .with_field(format!("{}{}", FILTER_PREFIX, path))
+    })?;
+    if !field.indexed {
+        let message = format!("{} is not an indexed custom field", field.path());
+        return Err(Error::new(message, 400).with_field(field.path()));
+    }
+    Ok(field)
+}
 
-        result.map(|u| u.id).map_err(|e| {
-            tracing::error!("Error on remove user: {:?}", e);
-            Error::new("This user does not exist".to_string(), 404)
+/// Condition of one filter, each served by the `users_custom_data` GIN index: equality goes through
+/// containment and existence through `?`. The index cannot compare, so ranges are narrowed down to
+/// the users having the key, then compared in a jsonpath, which only matches values of the field type.
+fn custom_data_condition(
+    fields: &[CustomField],
+    filter: &CustomDataFilter,
+    binds: &mut Vec<FilterBind>,
+) -> RepositoryResult<String> {
+    let invalid = |message: String| Error::new(message, 400).with_field(filter.field());
+    let field = filtered_custom_field(fields, &filter.path)?;
+    if filter.operator.is_range() && field.field_type == CustomFieldType::Boolean {
+        let message = format!("Operator {} is not supported by boolean fields", filter.operator.as_str());
+        return Err(invalid(message));
+    }
+    let value = |text: &str| {
+        field
+            .field_type
+            .value_from_str(text)
+            .ok_or_else(|| invalid(format!("{} must be of type {}", filter.field(), field.field_type.as_str())))
+    };
+    let contains = |value: serde_json::Value| {
+        let mut object = serde_json::Map::new();
+        object.insert(field.name.clone(), value);
+        FilterBind::Json(serde_json::Value::Object(object))
+    };
+
+    let condition = match filter.operator {
+        FilterOperator::Eq => format!("custom_data @> {}", push_bind(binds, contains(value(&filter.value)?))),
+        FilterOperator::Ne => format!("NOT custom_data @> {}", push_bind(binds, contains(value(&filter.value)?))),
+        FilterOperator::In => {
+            let mut alternatives = Vec::new();
+            for text in filter.values()? {
+                alternatives.push(format!("custom_data @> {}", push_bind(binds, contains(value(&text)?))));
+            }
+            format!("({})", alternatives.join(" OR "))
+        }
+        FilterOperator::Exists => {
+            let exists: bool = filter
+                .value
+                .parse()
+                .map_err(|_| invalid(format!("{}[exists] must be true or false", filter.field())))?;
+            let key = push_bind(binds, FilterBind::Text(field.name.clone()));
+            if exists {
+                format!("custom_data ? {}", key)
+            } else {
+                format!("NOT custom_data ? {}", key)
+            }
+        }
+        operator => {
+            let comparison = match operator {
+                FilterOperator::Gt => ">",
+                FilterOperator::Gte => ">=",
+                FilterOperator::Lt => "<",
+                _ => "<=",
+            };
+            let literal = jsonpath_literal(&value(&filter.value)?)
+                .ok_or_else(|| invalid(format!("{} must be of type {}", filter.field(), field.field_type.as_str())))?;
+            // Field names are restricted to lowercase letters, digits and underscores.
+            let path = format!("$.\"{}\" ? (@ {} {})", field.name, comparison, literal);
+            format!(
+                "(custom_data ? {} AND custom_data @? {}::jsonpath)",
+                push_bind(binds, FilterBind::Text(field.name.clone())),
+                push_bind(binds, FilterBind::Text(path))
+            )
+        }
+    };
+    Ok(condition)
+}
+
+/// Jsonpath literal of a range bound. JSON numbers and strings, escapes included, are valid
+/// jsonpath literals; other values have no order and are refused.
+fn jsonpath_literal(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Number(_) | serde_json::Value::String(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Compiles a filter expression into a query on `users`. Columns come from the fixed allowlist
+/// of fields and every value is bound, so nothing from the expression reaches the SQL text.
+fn filter_expression_query(
+    fields: &[CustomField],
+    email_rules: &EmailRules,
+    expression: &FilterExpression,
+) -> RepositoryResult<(String, Vec<FilterBind>)> {
+    let mut binds = Vec::new();
+    let condition = filter_expression_condition(fields, email_rules, expression, &mut binds)
+        .map_err(|err| err.with_field(FILTER_PARAMETER.to_string()))?;
+    Ok((format!("{} {}", GET_FILTERED_USERS_SQL, condition), binds))
+}
+
+fn filter_expression_condition(
+    fields: &[CustomField],
+    email_rules: &EmailRules,
+    expression: &FilterExpression,
+    binds: &mut Vec<FilterBind>,
+) -> RepositoryResult<String> {
+    let (field, comparison, value) = match expression {
+        FilterExpression::Not(inner) => {
+            return Ok(format!("NOT ({})", filter_expression_condition(fields, email_rules, inner, binds)?));
+        }
+        FilterExpression::And(left, right) | FilterExpression::Or(left, right) => {
+            let operator = if matches!(expression, FilterExpression::And(..)) { "AND" } else { "OR" };
+            return Ok(format!(
+                "({} {} {})",
+                filter_expression_condition(fields, email_rules, left, binds)?,
+                operator,
+                filter_expression_condition(fields, email_rules, right, binds)?
+            ));
+        }
+        FilterExpression::Compare { field, comparison, value } => (field, *comparison, value),
+    };
+
+    let column = match field {
+        FilterField::Name => "name",
+        FilterField::Email => "lower(email)",
+        FilterField::BirthDate => "birth_date",
+        FilterField::CreatedAt => "(created_at AT TIME ZONE 'UTC')::date",
+        FilterField::UpdatedAt => "(updated_at AT TIME ZONE 'UTC')::date",
+        FilterField::CustomData(path) if comparison == Comparison::Contains => {
+            let field = filtered_custom_field(fields, path)?;
+            if field.field_type != CustomFieldType::String {
+                let message = format!("Operator ~ is not supported by {} fields", field.field_type.as_str());
+                return Err(Error::new(message, 400).with_field(field.path()));
+            }
+            let key = push_bind(binds, FilterBind::Text(field.name.clone()));
+            let pattern = push_bind(binds, FilterBind::Text(like_pattern(&value.to_text())));
+            return Ok(format!("custom_data ->> {} ILIKE {}", key, pattern));
+        }
+        FilterField::CustomData(path) => {
+            let operator = match comparison {
+                Comparison::Eq => FilterOperator::Eq,
+                Comparison::Ne => FilterOperator::Ne,
+                Comparison::Lt => FilterOperator::Lt,
+                Comparison::Lte => FilterOperator::Lte,
+                Comparison::Gt => FilterOperator::Gt,
+                _ => FilterOperator::Gte,
+            };
+            let filter = CustomDataFilter { path: path.clone(), operator, value: value.to_text() };
+            return custom_data_condition(fields, &filter, binds);
+        }
+    };
+    let operator = comparison.as_str();
+    let condition = match (comparison, value) {
+        (Comparison::Contains, value) => {
+            format!("{} ILIKE {}", column, push_bind(binds, FilterBind::Text(like_pattern(&value.to_text()))))
+        }
+        (_, FilterValue::Date(date)) => {
+            format!("{} {} {}::date", column, operator, push_bind(binds, FilterBind::Text(date.to_string())))
+        }
+        (_, value) if *field == FilterField::Email => {
+            // Equality matches what `?email=` finds; an invalid email matches no stored one as is.
+            let text = value.to_text();
+            let text = match comparison {
+                Comparison::Eq | Comparison::Ne => email_rules.normalize(&text).unwrap_or(text),
+                _ => text,
+            };
+            format!("{} {} lower({})", column, operator, push_bind(binds, FilterBind::Text(text)))
+        }
+        (_, value) => format!("{} {} {}", column, operator, push_bind(binds, FilterBind::Text(value.to_text()))),
+    };
+    Ok(condition)
+}
+
+/// `ILIKE` pattern matching text containing `text`, its wildcards escaped.
+fn like_pattern(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn lease_end(now: DateTime<Utc>, lease: Duration) -> DateTime<Utc> {
+    now + chrono::Duration::from_std(lease).unwrap_or_else(|_| chrono::Duration::zero())
+}
+
+fn create_users_sql(rows: usize) -> String {
+    let values: Vec<String> = (0..rows)
+        .map(|row| {
+            let params: Vec<String> = (1..=6).map(|column| format!("${}", row * 6 + column)).collect();
+            format!("({})", params.join(", "))
         })
+        .collect();
+    format!("{} {} {}", CREATE_USERS_SQL, values.join(", "), CREATE_USERS_RETURNING)
+}
+
+async fn begin_savepoint<'t>(tx: &'t mut Transaction<'_, Postgres>) -> RepositoryResult<Transaction<'t, Postgres>> {
+    tx.begin().await.map_err(|e| map_write_error(e, "Error on bulk savepoint"))
+}
+
+/// Releases the savepoint when `result` is ok and rolls back to it otherwise.
+async fn end_savepoint<T>(savepoint: Transaction<'_, Postgres>, result: RepositoryResult<T>) -> RepositoryResult<T> {
+    let ended = if result.is_ok() {
+        savepoint.commit().await
+    } else {
+        savepoint.rollback().await
+    };
+    ended.map_err(|e| map_write_error(e, "Error on bulk savepoint"))?;
+    result
+}
+
+/// Client span of a repository call, carrying its SQL statement.
+fn repository_span(method: &str, statement: &str) -> tracing::Span {
+    tracing::info_span!(
+        "repository",
+        otel.name = %format!("Repository::{}", method),
+        otel.kind = "client",
+        db.system = "postgresql",
+        db.operation = %method,
+        db.statement = %RedactedSql(statement.trim()),
+    )
+}
+
+/// Maps a failed write, turning unique index violations into a 409 naming the resource and field.
+fn map_write_error(e: sqlx::Error, message: &str) -> Error {
+    if let sqlx::Error::Database(db_error) = &e {
+        if db_error.code().as_deref() == Some(UNIQUE_VIOLATION) {
+            let constraint = db_error.constraint().unwrap_or_default();
+            return match UNIQUE_FIELDS.iter().find(|(name, _, _)| *name == constraint) {
+                Some((_, resource, field)) => {
+                    tracing::warn!("{} with this {} already exists", resource, field);
+                    Error::new(format!("{} with this {} already exists", resource, field), 409)
+                        .with_field(field.to_string())
+                }
+                None => {
+                    tracing::warn!("{}, unique constraint {} violated", message, constraint);
+                    Error::new(format!("{}: the value already exists", message), 409)
+                }
+            };
+        }
+    }
+
+    map_read_error(e, message)
+}
+
+/// Maps a failed read; the database is a dependency, so its failures are a 502.
+fn map_read_error(e: sqlx::Error, message: &str) -> Error {
+    tracing::error!("{}, error: {:?}", message, RedactedDbError(&e));
+    Error::new(message.to_string(), 502)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::create_test_user;
+    use chrono::NaiveDate;
+    use std::borrow::Cow;
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct FakeDatabaseError {
+        code: &'static str,
+        constraint: &'static str,
+    }
+
+    impl fmt::Display for FakeDatabaseError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("duplicate key value violates unique constraint")
+        }
+    }
+
+    impl std::error::Error for FakeDatabaseError {}
+
+    impl sqlx::error::DatabaseError for FakeDatabaseError {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.code))
+        }
+
+        fn constraint(&self) -> Option<&str> {
+            Some(self.constraint)
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    fn database_error(code: &'static str, constraint: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(FakeDatabaseError { code, constraint }))
+    }
+
+    /// Repository on `DATABASE_URL`, for the ignored tests needing a migrated database, run with
+    /// `cargo test -- --include-ignored`.
+    async fn database_repository() -> PostgresRepository {
+        std::env::var("DATABASE_URL").expect("DATABASE_URL must point to a migrated database");
+        PostgresRepository::from_env().await.expect("Repository initialize error")
+    }
+
+    fn new_user(email: &str) -> CreateUser {
+        CreateUser {
+            email: email.to_string(),
+            name: "Meu nome".to_string(),
+            birth_date: NaiveDate::from_ymd(1977, 3, 10),
+            custom_data: serde_json::json!({"random": 1}),
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn unique_email() -> String {
+        format!("{}@teste.com", Uuid::new_v4())
+    }
+
+    #[test]
+    fn create_users_sql_numbers_binds_per_row() {
+        let sql = create_users_sql(2);
+        assert!(sql.contains("VALUES ($1, $2, $3, $4, $5, $6), ($7, $8, $9, $10, $11, $12) RETURNING"));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn bulk_keeps_operation_order() {
+        let repo = database_repository().await;
+        let existing = repo.create_user(&new_user(&unique_email())).await.unwrap();
+        let emails: Vec<String> = (0..3).map(|_| unique_email()).collect();
+        let mut operations: Vec<BulkOperation> = emails
+            .iter()
+            .map(|email| BulkOperation::Create { user: new_user(email) })
+            .collect();
+        operations.push(BulkOperation::Delete { id: existing.id });
+
+        let results = repo.bulk(&operations, BulkMode::Atomic).await.unwrap();
+
+        let created: Vec<&str> = results[..3].iter().map(|r| r.user.as_ref().unwrap().email.as_str()).collect();
+        assert_eq!(created, emails.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(results[3].status, 204);
+        assert_eq!(repo.get_user(&existing.id).await.unwrap_err().status, 404);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn atomic_bulk_rolls_back_on_failure() {
+        let repo = database_repository().await;
+        let email = unique_email();
+        let operations = vec![
+            BulkOperation::Create { user: new_user(&email) },
+            BulkOperation::Create { user: new_user(&unique_email()) },
+            BulkOperation::Delete { id: Uuid::new_v4() },
+        ];
+
+        let err = repo.bulk(&operations, BulkMode::Atomic).await.unwrap_err();
+
+        assert_eq!(err.status, 404);
+        assert_eq!(err.field.as_deref(), Some("operations[2]"));
+        assert_eq!(repo.get_user_by_email(&email).await.unwrap_err().status, 404);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn best_effort_bulk_reports_each_failure() {
+        let repo = database_repository().await;
+        let email = unique_email();
+        let operations = vec![
+            BulkOperation::Create { user: new_user(&email) },
+            BulkOperation::Create { user: new_user(&email.to_uppercase()) },
+            BulkOperation::Create { user: new_user("invalid") },
+            BulkOperation::Create { user: new_user(&unique_email()) },
+        ];
+
+        let results = repo.bulk(&operations, BulkMode::BestEffort).await.unwrap();
+
+        let statuses: Vec<u16> = results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, vec![201, 409, 422, 201]);
+        assert_eq!(results[1].error.as_ref().unwrap().field.as_deref(), Some("email"));
+        assert!(repo.get_user_by_email(&email).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn custom_data_is_stored_as_json_and_checked_against_the_schema() {
+        let repo = database_repository().await;
+        let schema = serde_json::json!({"type": "object", "properties": {"random": {"type": "integer"}}});
+        let repo = PostgresRepository { custom_data_schema: CustomDataSchema::new(&schema).unwrap(), ..repo };
+        let mut user = new_user(&unique_email());
+        user.custom_data = serde_json::json!({"random": 2, "tags": ["a", "b"], "address": {"city": "Rio"}});
+
+        let created = repo.create_user(&user).await.unwrap();
+        let stored = repo.get_user(&created.id).await.unwrap().custom_data;
+        assert_eq!(stored, created.custom_data);
+        for key in ["random", "tags", "address"] {
+            assert_eq!(stored[key], user.custom_data[key]);
+        }
+
+        user.email = unique_email();
+        user.custom_data = serde_json::json!({"random": "dois"});
+        let err = repo.create_user(&user).await.unwrap_err();
+        assert_eq!(err.status, 422);
+        assert_eq!(err.field.as_deref(), Some("custom_data.random"));
+
+        let changes = PatchUser { custom_data: Some(serde_json::json!([])), ..PatchUser::default() };
+        let err = repo.patch_user(&created.id, &changes, created.updated_at).await.unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("custom_data"));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn custom_fields_are_cached_until_changed_here() {
+        let (repo, other) = (database_repository().await, database_repository().await);
+        let field = |name: String| -> CustomField {
+            serde_json::from_value(serde_json::json!({"name": name, "type": "string"})).unwrap()
+        };
+        let suffix = Uuid::new_v4().to_simple().to_string();
+        let names = |fields: Vec<CustomField>| fields.into_iter().map(|field| field.name).collect::<Vec<_>>();
+        repo.custom_fields().await.unwrap();
+
+        let elsewhere = other.create_custom_field(&field(format!("elsewhere_{}", suffix))).await.unwrap();
+        assert!(!names(repo.custom_fields().await.unwrap()).contains(&elsewhere.name));
+
+        let here = repo.create_custom_field(&field(format!("here_{}", suffix))).await.unwrap();
+        let cached = names(repo.custom_fields().await.unwrap());
+        assert!(cached.contains(&elsewhere.name) && cached.contains(&here.name));
+
+        repo.delete_custom_field(&here.name).await.unwrap();
+        assert!(!names(repo.custom_fields().await.unwrap()).contains(&here.name));
+        other.delete_custom_field(&elsewhere.name).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn custom_field_changes_stored_users_may_not_satisfy_conflict() {
+        let repo = database_repository().await;
+        let tier: CustomField = serde_json::from_value(serde_json::json!({
+            "name": format!("tier_{}", Uuid::new_v4().to_simple()), "type": "string", "enum": ["free", "pro"]
+        }))
+        .unwrap();
+        repo.create_custom_field(&tier).await.unwrap();
+        let widened = CustomField { enum_values: None, ..tier.clone() };
+        repo.update_custom_field(&widened).await.unwrap();
+        let err = repo.update_custom_field(&CustomField { required: true, ..widened.clone() }).await.unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (409, Some("required")));
+
+        let mut user = new_user(&unique_email());
+        user.custom_data = serde_json::json!({ tier.name.clone(): "gold" });
+        repo.create_user(&user).await.unwrap();
+        let err = repo.update_custom_field(&tier).await.unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (409, Some("enum")));
+        let err = repo
+            .update_custom_field(&CustomField { field_type: CustomFieldType::Integer, ..widened.clone() })
+            .await
+            .unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (409, Some("type")));
+        assert_eq!(repo.get_custom_field(&tier.name).await.unwrap().enum_values, None);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn custom_fields_are_applied_on_write_and_filter_users() {
+        let repo = database_repository().await;
+        // Unique, optional field names keep users created by concurrent tests valid.
+        let suffix = Uuid::new_v4().to_simple().to_string();
+        let plan: CustomField = serde_json::from_value(serde_json::json!({
+            "name": format!("plan_{}", suffix), "type": "string", "enum": ["free", "pro"], "default": "free", "indexed": true
+        }))
+        .unwrap();
+        let score: CustomField =
+            serde_json::from_value(serde_json::json!({"name": format!("score_{}", suffix), "type": "integer"})).unwrap();
+        repo.create_custom_field(&plan).await.unwrap();
+        repo.create_custom_field(&score).await.unwrap();
+        assert_eq!(repo.create_custom_field(&plan).await.unwrap_err().status, 409);
+
+        let created = repo.create_user(&new_user(&unique_email())).await.unwrap();
+        assert_eq!(created.custom_data[&plan.name], "free");
+
+        let mut user = new_user(&unique_email());
+        user.custom_data = serde_json::json!({ plan.name.clone(): "pro", score.name.clone(): 10 });
+        let pro = repo.create_user(&user).await.unwrap();
+        user.custom_data = serde_json::json!({ plan.name.clone(): "gold" });
+        assert_eq!(repo.create_user(&user).await.unwrap_err().field, Some(plan.path()));
+
+        let filter = |key: &str, value: &str| CustomDataFilter::parse(&format!("custom_data.{}", key), value).unwrap().unwrap();
+        let filters = vec![filter(&plan.name, "pro")];
+        let found = repo.get_users_by_custom_data(&filters).await.unwrap();
+        assert_eq!(found.iter().map(|user| user.id).collect::<Vec<_>>(), vec![pro.id]);
+        let filters = vec![filter(&score.name, "10")];
+        assert_eq!(repo.get_users_by_custom_data(&filters).await.unwrap_err().status, 400);
+
+        let updated = repo.update_custom_field(&CustomField { indexed: true, ..score.clone() }).await.unwrap();
+        assert!(updated.indexed && updated.updated_at.is_some());
+        assert_eq!(repo.get_users_by_custom_data(&filters).await.unwrap().len(), 1);
+
+        // Users created meanwhile by other tests get the plan default too, so only ours are checked.
+        let user_ids = |filters: Vec<CustomDataFilter>| {
+            let repo = &repo;
+            let ours = [created.id, pro.id];
+            async move {
+                let users = repo.get_users_by_custom_data(&filters).await.unwrap();
+                users.iter().map(|user| user.id).filter(|id| ours.contains(id)).collect::<Vec<Uuid>>()
+            }
+        };
+        let range = format!("{}[gte]", score.name);
+        assert_eq!(user_ids(vec![filter(&range, "10")]).await, vec![pro.id]);
+        assert!(user_ids(vec![filter(&format!("{}[gt]", score.name), "10")]).await.is_empty());
+        assert_eq!(user_ids(vec![filter(&format!("{}[lt]", plan.name), "pro\"\\")]).await.len(), 2);
+        let either = filter(&format!("{}[in]", plan.name), "free,pro");
+        assert_eq!(user_ids(vec![either.clone()]).await.len(), 2);
+        let ne = vec![filter(&format!("{}[ne]", plan.name), "pro"), either.clone()];
+        assert_eq!(user_ids(ne).await, vec![created.id]);
+        let missing = vec![filter(&format!("{}[exists]", score.name), "false"), either];
+        assert_eq!(user_ids(missing).await, vec![created.id]);
+
+        let err = repo.get_users_by_custom_data(&[filter("unknown", "1")]).await.unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (400, Some("custom_data.unknown")));
+        let err = repo.get_users_by_custom_data(&[filter(&range, "dez")]).await.unwrap_err();
+        assert_eq!(err.status, 400);
+        assert_eq!(repo.delete_custom_field(&plan.name).await.unwrap(), plan.name);
+        assert_eq!(repo.delete_custom_field(&score.name).await.unwrap(), score.name);
+        assert_eq!(repo.get_custom_field(&plan.name).await.unwrap_err().status, 404);
+    }
+
+    #[test]
+    fn filter_expression_query_binds_every_value() {
+        let score: CustomField =
+            serde_json::from_value(serde_json::json!({"name": "score", "type": "integer", "indexed": true})).unwrap();
+        let expression = FilterExpression::parse(
+            r#"name ~ "50%_off" and (birth_date < 1990-01-01 or custom_data.score > 5) or not email = "A@B.com""#,
+        )
+        .unwrap();
+        let rules = EmailRules::default();
+        let (sql, binds) = filter_expression_query(std::slice::from_ref(&score), &rules, &expression).unwrap();
+        assert_eq!(
+            sql,
+            format!(
+                "{} ((name ILIKE $1 AND (birth_date < $2::date OR (custom_data ? $3 AND custom_data @? $4::jsonpath))) \
+                 OR NOT (lower(email) = lower($5)))",
+                GET_FILTERED_USERS_SQL
+            )
+        );
+        assert_eq!(binds[0], FilterBind::Text("%50\\%\\_off%".to_string()));
+        assert_eq!(binds[1], FilterBind::Text("1990-01-01".to_string()));
+        assert_eq!(binds[2], FilterBind::Text("score".to_string()));
+        assert_eq!(binds[3], FilterBind::Text("$.\"score\" ? (@ > 5)".to_string()));
+        assert_eq!(binds[4], FilterBind::Text("A@b.com".to_string()));
+
+        let gmail = FilterExpression::parse(r#"email = " A.B+news@GMail.com""#).unwrap();
+        let (_, binds) = filter_expression_query(&[], &EmailRules { provider_rules: true }, &gmail).unwrap();
+        assert_eq!(binds, vec![FilterBind::Text("ab@gmail.com".to_string())]);
+
+        let unindexed = CustomField { indexed: false, ..score };
+        let expression = FilterExpression::parse("custom_data.score = 1").unwrap();
+        let err = filter_expression_query(&[unindexed], &rules, &expression).unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (400, Some("filter")));
+        let err = filter_expression_query(&[], &rules, &FilterExpression::parse(r#"custom_data.plan ~ "p""#).unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "Unknown custom data path custom_data.plan");
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn get_users_by_filter_expression() {
+        let repo = database_repository().await;
+        let suffix = Uuid::new_v4().to_simple().to_string();
+        let level: CustomField = serde_json::from_value(
+            serde_json::json!({"name": format!("level_{}", suffix), "type": "integer", "indexed": true}),
+        )
+        .unwrap();
+        repo.create_custom_field(&level).await.unwrap();
+
+        let mut young = CreateUser { name: format!("Ana {}", suffix), ..new_user(&unique_email()) };
+        young.birth_date = NaiveDate::from_ymd(1995, 5, 1);
+        young.custom_data = serde_json::json!({ level.name.clone(): 7 });
+        let young = repo.create_user(&young).await.unwrap();
+        let old = CreateUser { name: format!("Bia {}", suffix), ..new_user(&unique_email()) };
+        let old = repo.create_user(&old).await.unwrap();
+
+        let ids = |filter: String| {
+            let repo = &repo;
+            async move {
+                let filter = FilterExpression::parse(&filter).unwrap();
+                let users = repo.get_users_by_filter(&filter).await.unwrap();
+                let mut ids: Vec<Uuid> = users.iter().map(|user| user.id).collect();
+                ids.sort();
+                ids
+            }
+        };
+        let mut both = vec![young.id, old.id];
+        both.sort();
+        assert_eq!(ids(format!(r#"name ~ "{}""#, suffix)).await, both);
+        assert_eq!(ids(format!(r#"name ~ "{}" and birth_date < 1990-01-01"#, suffix)).await, vec![old.id]);
+        let filter = format!(r#"name ~ "{}" and (birth_date < 1980-01-01 or custom_data.{} > 5)"#, suffix, level.name);
+        assert_eq!(ids(filter).await, both);
+        let filter = format!(r#"name ~ "{}" and not custom_data.{} = 7"#, suffix, level.name);
+        assert_eq!(ids(filter).await, vec![old.id]);
+        let filter = format!(r#"email = "{}""#, old.email.to_uppercase());
+        assert_eq!(ids(filter).await, vec![old.id]);
+
+        repo.delete_user(&young.id).await.unwrap();
+        repo.delete_user(&old.id).await.unwrap();
+        repo.delete_custom_field(&level.name).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn get_page_and_count_users() {
+        let repo = database_repository().await;
+        let created = repo.create_user(&new_user(&unique_email())).await.unwrap();
+
+        let exact = repo.count_users(CountMode::Exact).await.unwrap();
+        assert!(exact.count >= 1 && !exact.estimated);
+        let estimated = repo.count_users(CountMode::Estimated).await.unwrap();
+        assert!(estimated.count >= 0);
+
+        let page = repo.get_page(&Page { limit: 2, offset: 0 }).await.unwrap();
+        assert!(!page.is_empty() && page.len() <= 2);
+        assert!(page.windows(2).all(|pair| pair[0].id < pair[1].id));
+        let beyond = repo.get_page(&Page { limit: 2, offset: i32::MAX as i64 }).await.unwrap();
+        assert!(beyond.is_empty());
+
+        repo.delete_user(&created.id).await.unwrap();
+        assert_eq!(repo.delete_user(&created.id).await.unwrap_err().status, 404);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn import_records_batches_until_finished() {
+        let repo = database_repository().await;
+        let created = repo.create_import(b"email,name,birth_date,custom_data.random\n", 3).await.unwrap();
+        assert_eq!(created.status, ImportStatus::Pending);
+
+        // Jobs left by other runs are claimed first; a short lease frees them again.
+        let lease = Duration::from_secs(1);
+        let (job, data) = loop {
+            let (job, data) = repo.claim_import(lease).await.unwrap().unwrap();
+            if job.id == created.id {
+                break (job, data);
+            }
+        };
+        assert_eq!(job.status, ImportStatus::Running);
+        assert!(data.starts_with(b"email,"));
+
+        let email = unique_email();
+        let batch = ImportBatch {
+            from_row: 0,
+            processed_rows: 3,
+            users: vec![(2, new_user(&email)), (3, new_user(&email))],
+            failures: vec![ImportError::new(4, Error::new("Invalid email".to_string(), 422))],
+            lease,
+        };
+        let job = repo.import_batch(&job.id, &batch).await.unwrap();
+        assert_eq!((job.processed_rows, job.created_rows, job.failed_rows), (3, 1, 2));
+        let lines: Vec<u64> = job.errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![3, 4]);
+        assert_eq!(job.errors[0].status, 409);
+
+        // The same batch from a worker whose lease expired is not counted again.
+        let job = repo.import_batch(&job.id, &batch).await.unwrap();
+        assert_eq!((job.processed_rows, job.created_rows, job.failed_rows), (3, 1, 2));
+
+        let job = repo.finish_import(&job.id, ImportStatus::Completed, None).await.unwrap();
+        assert_eq!(job.status, ImportStatus::Completed);
+        assert_eq!(repo.cancel_import(&job.id).await.unwrap_err().status, 409);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn cancelled_import_ignores_batches() {
+        let repo = database_repository().await;
+        let job = repo.create_import(b"email,name,birth_date,custom_data.random\n", 1).await.unwrap();
+
+        let cancelled = repo.cancel_import(&job.id).await.unwrap();
+        assert_eq!(cancelled.status, ImportStatus::Cancelled);
+
+        let email = unique_email();
+        let batch = ImportBatch {
+            from_row: 0,
+            processed_rows: 1,
+            users: vec![(2, new_user(&email))],
+            failures: Vec::new(),
+            lease: Duration::from_secs(1),
+        };
+        let job = repo.import_batch(&job.id, &batch).await.unwrap();
+        assert_eq!((job.status, job.processed_rows), (ImportStatus::Cancelled, 0));
+        assert_eq!(repo.get_user_by_email(&email).await.unwrap_err().status, 404);
+        assert_eq!(repo.cancel_import(&Uuid::new_v4()).await.unwrap_err().status, 404);
+    }
+
+    #[test]
+    fn unique_violation_on_email_is_conflict() {
+        let err = map_write_error(database_error(UNIQUE_VIOLATION, "users_email"), "Error on create user");
+        assert_eq!(err.status, 409);
+        assert_eq!(err.field.as_deref(), Some("email"));
+    }
+
+    #[test]
+    fn unique_violation_on_primary_key_is_conflict() {
+        let err = map_write_error(database_error(UNIQUE_VIOLATION, "users_pkey"), "Error on create user");
+        assert_eq!(err.status, 409);
+        assert_eq!(err.field.as_deref(), Some("id"));
+    }
+
+    #[test]
+    fn unique_violation_names_the_resource() {
+        let err = map_write_error(database_error(UNIQUE_VIOLATION, "custom_fields_pkey"), "Error on create field");
+        assert_eq!(err.message, "A custom field with this name already exists");
+        assert_eq!(err.field.as_deref(), Some("name"));
+        let err = map_write_error(database_error(UNIQUE_VIOLATION, "imports_pkey"), "Error on create import");
+        assert_eq!(err.message, "An import with this id already exists");
+        let err = map_write_error(database_error(UNIQUE_VIOLATION, "unknown_key"), "Error on create import");
+        assert_eq!((err.status, err.field), (409, None));
+    }
+
+    #[test]
+    fn other_database_errors_are_bad_gateway() {
+        let err = map_write_error(database_error("23502", "users_name"), "Error on create user");
+        assert_eq!(err.status, 502);
+        assert_eq!(err.field, None);
+        assert_eq!(map_write_error(sqlx::Error::PoolTimedOut, "Error on create user").status, 502);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn concurrent_creates_with_same_email_conflict() {
+        let repo = database_repository().await;
+        let user = new_user(&unique_email());
+
+        let results = futures::future::join_all((0..8).map(|_| repo.create_user(&user))).await;
+
+        let created = results.iter().filter(|result| result.is_ok()).count();
+        assert_eq!(created, 1);
+        for err in results.iter().filter_map(|result| result.as_ref().err()) {
+            assert_eq!(err.status, 409);
+            assert_eq!(err.field.as_deref(), Some("email"));
+        }
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn get_user_by_email_finds_created_user() {
+        let repo = database_repository().await;
+        let created = repo.create_user(&new_user(&unique_email())).await.unwrap();
+
+        let found = repo.get_user_by_email(&created.email).await.unwrap();
+        assert_eq!(found.id, created.id);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn emails_are_normalized_and_unique_ignoring_case() {
+        let repo = database_repository().await;
+        let local = Uuid::new_v4().to_string();
+
+        let created = repo
+            .create_user(&new_user(&format!(" {}@Teste.COM ", local)))
+            .await
+            .unwrap();
+        assert_eq!(created.email, format!("{}@teste.com", local));
+
+        let found = repo
+            .get_user_by_email(&format!("{}@TESTE.com", local.to_uppercase()))
+            .await
+            .unwrap();
+        assert_eq!(found.id, created.id);
+
+        let err = repo
+            .create_user(&new_user(&format!("{}@teste.com", local.to_uppercase())))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, 409);
+        assert_eq!(err.field.as_deref(), Some("email"));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn provider_rules_normalize_stored_emails() {
+        let repo = database_repository().await;
+        let local = Uuid::new_v4().to_simple().to_string();
+        let created = repo
+            .create_user(&new_user(&format!("{}.Ana+news@GoogleMail.com", local)))
+            .await
+            .unwrap();
+        assert_eq!(created.email, format!("{}.Ana+news@googlemail.com", local));
+
+        let repo = PostgresRepository {
+            email_rules: EmailRules { provider_rules: true },
+            ..repo
+        };
+        repo.normalize_stored_emails().await.unwrap();
+
+        let found = repo.get_user_by_email(&format!("{}ana@gmail.com", local)).await.unwrap();
+        assert_eq!(found.id, created.id);
+        assert_eq!(found.email, format!("{}ana@gmail.com", local));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn update_to_another_users_email_conflicts() {
+        let repo = database_repository().await;
+        let first = repo.create_user(&new_user(&unique_email())).await.unwrap();
+        let mut second = repo.create_user(&new_user(&unique_email())).await.unwrap();
+
+        second.email = first.email.clone();
+        let err = repo.update_user(&second).await.unwrap_err();
+        assert_eq!(err.status, 409);
+        assert_eq!(err.field.as_deref(), Some("email"));
+
+        let unchanged = repo.update_user(&first).await.unwrap();
+        assert_eq!(unchanged.email, first.email);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn concurrent_updates_to_same_email_conflict() {
+        let repo = database_repository().await;
+        let email = unique_email();
+        let mut users = Vec::new();
+        for _ in 0..4 {
+            let mut user = repo.create_user(&new_user(&unique_email())).await.unwrap();
+            user.email = email.clone();
+            users.push(user);
+        }
+
+        let results = futures::future::join_all(users.iter().map(|user| repo.update_user(user))).await;
+
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .all(|err| err.status == 409));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn patch_only_changes_supplied_fields() {
+        let repo = database_repository().await;
+        let created = repo.create_user(&new_user(&unique_email())).await.unwrap();
+
+        let changes = PatchUser {
+            birth_date: Some(NaiveDate::from_ymd(1980, 1, 2)),
+            ..PatchUser::default()
+        };
+        let patched = repo.patch_user(&created.id, &changes, created.updated_at).await.unwrap();
+
+        assert_eq!(patched.birth_date, NaiveDate::from_ymd(1980, 1, 2));
+        assert_eq!(patched.name, created.name);
+        assert_eq!(patched.email, created.email);
+        assert!(patched.updated_at.is_some());
+        assert_eq!(repo.patch_user(&Uuid::new_v4(), &changes, None).await.unwrap_err().status, 404);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn interleaved_patches_do_not_lose_updates() {
+        let repo = database_repository().await;
+        let created = repo.create_user(&new_user(&unique_email())).await.unwrap();
+
+        // Both patches are computed from the same read of the user.
+        let read = repo.get_user(&created.id).await.unwrap();
+        let (_, first) = PatchUser::merge(&read, &serde_json::json!({"custom_data": {"team": "core"}})).unwrap();
+        let (_, second) = PatchUser::merge(&read, &serde_json::json!({"custom_data": {"level": 3}})).unwrap();
+
+        let patched = repo.patch_user(&created.id, &first, read.updated_at).await.unwrap();
+        let err = repo.patch_user(&created.id, &second, read.updated_at).await.unwrap_err();
+        assert_eq!(err.status, 409);
+        assert_eq!(repo.get_user(&created.id).await.unwrap().custom_data, patched.custom_data);
+
+        // Retried from a fresh read, the second patch keeps the first one's change.
+        let (_, second) = PatchUser::merge(&patched, &serde_json::json!({"custom_data": {"level": 3}})).unwrap();
+        let patched = repo.patch_user(&created.id, &second, patched.updated_at).await.unwrap();
+        assert_eq!(patched.custom_data, serde_json::json!({"random": 1, "team": "core", "level": 3}));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn export_streams_every_user() {
+        let repo = database_repository().await;
+        let created = repo.create_user(&new_user(&unique_email())).await.unwrap();
+
+        let exported: Vec<User> = repo.export_users().map(Result::unwrap).collect().await;
+        assert!(exported.iter().any(|user| user.id == created.id));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn get_users_returns_only_existing_ids() {
+        let repo = database_repository().await;
+        let first = repo.create_user(&new_user(&unique_email())).await.unwrap();
+        let second = repo.create_user(&new_user(&unique_email())).await.unwrap();
+
+        let mut ids: Vec<Uuid> = repo
+            .get_users(&[first.id, Uuid::new_v4(), second.id])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|user| user.id)
+            .collect();
+        ids.sort();
+        let mut expected = vec![first.id, second.id];
+        expected.sort();
+        assert_eq!(ids, expected);
+        assert!(repo.get_users(&[]).await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn upsert_creates_then_replaces() {
+        let repo = database_repository().await;
+        let mut user = create_test_user(Uuid::new_v4(), "Meu nome".to_string(), (1977, 3, 10));
+        user.email = unique_email();
+
+        let (created, was_created) = repo.upsert_user(&user).await.unwrap();
+        assert!(was_created);
+        assert_eq!(created.id, user.id);
+        assert!(created.updated_at.is_none());
+
+        user.name = "Outro nome".to_string();
+        let (replaced, was_created) = repo.upsert_user(&user).await.unwrap();
+        assert!(!was_created);
+        assert_eq!(replaced.name, "Outro nome");
+        assert_eq!(replaced.created_at, created.created_at);
+        assert!(replaced.updated_at.is_some());
+
+        let mut other = create_test_user(Uuid::new_v4(), "Meu nome".to_string(), (1977, 3, 10));
+        other.email = user.email.clone();
+        let err = repo.upsert_user(&other).await.unwrap_err();
+        assert_eq!(err.status, 409);
+        assert_eq!(err.field.as_deref(), Some("email"));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn update_missing_user_is_not_found() {
+        let repo = database_repository().await;
+        let mut user = repo.create_user(&new_user(&unique_email())).await.unwrap();
+        user.id = Uuid::new_v4();
+
+        let err = repo.update_user(&user).await.unwrap_err();
+        assert_eq!(err.status, 404);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs DATABASE_URL pointing to a migrated database"]
+    async fn search_users_by_prefix_and_typo() {
+        let repo = database_repository().await;
+        let word = format!("zz{}", Uuid::new_v4().to_simple());
+        let user = CreateUser { name: format!("Ana {}", word), ..new_user(&unique_email()) };
+        let created = repo.create_user(&user).await.unwrap();
+
+        let query = SearchQuery { q: word[..12].to_uppercase(), limit: None };
+        let hits = repo.search_users(&query).await.unwrap();
+        let hit = hits.iter().find(|hit| hit.user.id == created.id).unwrap();
+        assert!(hit.rank > 0.0);
+        assert_eq!(hit.highlights.name, format!("Ana <mark>{}</mark>", word));
+
+        let typo = format!("{}{}", &word[..10], &word[11..]);
+        let hits = repo.search_users(&SearchQuery { q: typo, limit: Some(5) }).await.unwrap();
+        assert!(hits.iter().any(|hit| hit.user.id == created.id));
+
+        repo.delete_user(&created.id).await.unwrap();
     }
 }
@@ -0,0 +1,240 @@
+use std::fmt;
+use std::future::{ready, Ready};
+
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use futures::future::LocalBoxFuture;
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Identifier of the request being served, taken from `X-Request-Id` or generated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?.trim();
+        let valid = !value.is_empty()
+            && value.len() <= MAX_REQUEST_ID_LEN
+            && value.chars().all(|c| c.is_ascii_graphic());
+        valid.then(|| Self(value.to_string()))
+    }
+
+    fn generate() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(RequestId::generate);
+        ready(Ok(request_id))
+    }
+}
+
+/// Middleware tagging every request with a [`RequestId`].
+///
+/// The id is echoed in the `X-Request-Id` response header and recorded on a `request` span
+/// wrapping the rest of the chain, so every event logged while serving it carries the id.
+pub struct RequestIdMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIdMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestIdService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdService { service }))
+    }
+}
+
+pub struct RequestIdService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(RequestId::generate);
+        req.extensions_mut().insert(request_id.clone());
+
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+        );
+        let fut = self.service.call(req).instrument(span);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(mut res) => {
+                    insert_header(res.headers_mut(), &request_id);
+                    Ok(res)
+                }
+                Err(err) => {
+                    let mut res = err.error_response();
+                    insert_header(res.headers_mut(), &request_id);
+                    Err(InternalError::from_response(err, res).into())
+                }
+            }
+        })
+    }
+}
+
+fn insert_header(headers: &mut HeaderMap, request_id: &RequestId) {
+    if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+        headers.insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MockRepository;
+    use actix_web::http::StatusCode;
+    use actix_web::web;
+    use actix_web::{App, HttpResponse};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    const REQUEST_ID: &str = "71802ecd-4eb3-4381-af7e-f737e3a35d5d";
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn header(res: &ServiceResponse<impl actix_web::body::MessageBody>) -> Option<&str> {
+        res.headers().get(REQUEST_ID_HEADER).and_then(|h| h.to_str().ok())
+    }
+
+    async fn logging_handler(request_id: RequestId) -> HttpResponse {
+        tracing::info!("Handling request");
+        HttpResponse::Ok().body(request_id.to_string())
+    }
+
+    #[actix_rt::test]
+    async fn request_id_is_echoed_from_request_header() {
+        let app = App::new()
+            .wrap(RequestIdMiddleware)
+            .route("/", web::get().to(logging_handler));
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, REQUEST_ID))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res), Some(REQUEST_ID));
+        let body = actix_web::test::read_body(res).await;
+        assert_eq!(body, REQUEST_ID.as_bytes());
+    }
+
+    #[actix_rt::test]
+    async fn request_id_is_generated_when_missing_or_invalid() {
+        let app = App::new()
+            .wrap(RequestIdMiddleware)
+            .route("/", web::get().to(logging_handler));
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "has spaces"))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        let request_id = header(&res).unwrap();
+        assert!(uuid::Uuid::parse_str(request_id).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn request_id_is_returned_on_path_errors() {
+        let app = App::new()
+            .wrap(RequestIdMiddleware)
+            .app_data(web::Data::new(MockRepository::default()))
+            .configure(crate::v1::service::<MockRepository>);
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/v1/user/not-a-uuid")
+            .insert_header((REQUEST_ID_HEADER, REQUEST_ID))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(header(&res), Some(REQUEST_ID));
+    }
+
+    #[actix_rt::test]
+    async fn request_id_is_recorded_on_log_events() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = App::new()
+            .wrap(RequestIdMiddleware)
+            .route("/", web::get().to(logging_handler));
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, REQUEST_ID))
+            .to_request();
+        actix_web::test::call_service(&app, req).await;
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = logs.lines().find(|l| l.contains("Handling request")).unwrap();
+        assert!(line.contains(&format!(r#""request_id":"{}""#, REQUEST_ID)));
+    }
+}
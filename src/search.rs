@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::user::User;
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+/// Query string of `GET /v1/user/search`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+impl SearchQuery {
+    pub fn validate(&self) -> Result<(), Error> {
+        if terms(&self.q).is_empty() {
+            return Err(Error::new("Search must have at least a letter or digit".to_string(), 400)
+                .with_field("q".to_string()));
+        }
+        if let Some(limit) = self.limit.filter(|limit| !(1..=MAX_LIMIT).contains(limit)) {
+            let message = format!("Limit must be between 1 and {}, got {}", MAX_LIMIT, limit);
+            return Err(Error::new(message, 400).with_field("limit".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT)
+    }
+
+    /// Full-text query matching every term as a prefix, e.g. `ana:* & silva:*`.
+    pub fn prefix_tsquery(&self) -> String {
+        terms(&self.q).iter().map(|term| format!("{}:*", term)).collect::<Vec<_>>().join(" & ")
+    }
+}
+
+/// A user found by a search, best matches having the highest rank.
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub user: User,
+    pub rank: f32,
+    pub highlights: SearchHighlights,
+}
+
+/// Name and email with the words matching a search term wrapped in `<mark>`, HTML-escaped.
+#[derive(Debug, Serialize)]
+pub struct SearchHighlights {
+    pub name: String,
+    pub email: String,
+}
+
+impl SearchHit {
+    pub fn new(user: User, rank: f32, query: &SearchQuery) -> Self {
+        let terms = terms(&query.q);
+        let highlights = SearchHighlights { name: highlight(&user.name, &terms), email: highlight(&user.email, &terms) };
+        Self { user, rank, highlights }
+    }
+}
+
+/// Lowercase words of a text: runs of letters and digits.
+pub fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Wraps the words of `text` starting with one of `terms` in `<mark>`, escaping the rest as HTML.
+fn highlight(text: &str, terms: &[String]) -> String {
+    let mut highlighted = String::with_capacity(text.len());
+    let mut word = String::new();
+    let flush = |word: &mut String, highlighted: &mut String| {
+        if word.is_empty() {
+            return;
+        }
+        let lowercase = word.to_lowercase();
+        if terms.iter().any(|term| lowercase.starts_with(term.as_str())) {
+            highlighted.push_str("<mark>");
+            highlighted.push_str(word);
+            highlighted.push_str("</mark>");
+        } else {
+            highlighted.push_str(word);
+        }
+        word.clear();
+    };
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+        flush(&mut word, &mut highlighted);
+        match c {
+            '&' => highlighted.push_str("&amp;"),
+            '<' => highlighted.push_str("&lt;"),
+            '>' => highlighted.push_str("&gt;"),
+            '"' => highlighted.push_str("&quot;"),
+            '\'' => highlighted.push_str("&#39;"),
+            c => highlighted.push(c),
+        }
+    }
+    flush(&mut word, &mut highlighted);
+    highlighted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(q: &str) -> SearchQuery {
+        SearchQuery { q: q.to_string(), limit: None }
+    }
+
+    #[test]
+    fn prefix_tsquery_keeps_only_words() {
+        assert_eq!(query(" Ana  da-Silva'); drop").prefix_tsquery(), "ana:* & da:* & silva:* & drop:*");
+    }
+
+    #[test]
+    fn validate_requires_a_word_and_a_bounded_limit() {
+        assert_eq!(query(" %% ").validate().unwrap_err().field.as_deref(), Some("q"));
+        let err = SearchQuery { limit: Some(0), ..query("ana") }.validate().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("limit"));
+        assert!(query("ana").validate().is_ok());
+    }
+
+    #[test]
+    fn highlight_marks_matching_words_and_escapes_html() {
+        let terms = terms("ana");
+        assert_eq!(highlight("Anabela <Souza> & ana", &terms), "<mark>Anabela</mark> &lt;Souza&gt; &amp; <mark>ana</mark>");
+        assert_eq!(highlight("ana.souza@teste.com", &terms), "<mark>ana</mark>.souza@teste.com");
+    }
+}
@@ -0,0 +1,298 @@
+use std::future::{ready, Ready};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use futures::future::LocalBoxFuture;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self as sdktrace, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_SERVICE_NAME: &str = "my-api";
+const OTLP_TRACES_PATH: &str = "/v1/traces";
+
+pub struct TelemetryConfig {
+    /// Base URL of the OTLP/HTTP collector; spans are only exported when it is set.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Self {
+        Self {
+            otlp_endpoint: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .ok()
+                .filter(|endpoint| !endpoint.is_empty()),
+            service_name: std::env::var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|_| DEFAULT_SERVICE_NAME.to_string()),
+        }
+    }
+}
+
+/// Installs the global tracing subscriber: pretty logs on debug builds, JSON on release, plus an
+/// OpenTelemetry layer when an OTLP endpoint is configured.
+///
+/// Returns the tracer provider so it can be flushed on shutdown.
+pub fn init(config: &TelemetryConfig) -> Option<TracerProvider> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = config.otlp_endpoint.as_deref().and_then(|endpoint| {
+        tracer_provider(endpoint, &config.service_name)
+            .map_err(|err| eprintln!("Error on create OTLP exporter for {}: {:?}", endpoint, err))
+            .ok()
+    });
+    let otel = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(DEFAULT_SERVICE_NAME))
+    });
+
+    let timer = tracing_subscriber::fmt::time::UtcTime::rfc_3339();
+    let (pretty, json) = if cfg!(debug_assertions) {
+        (Some(tracing_subscriber::fmt::layer().with_timer(timer).pretty()), None)
+    } else {
+        let json = tracing_subscriber::fmt::layer()
+            .with_timer(timer)
+            .json()
+            .with_current_span(true)
+            .with_span_list(true);
+        (None, Some(json))
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(otel)
+        .with(pretty)
+        .with(json)
+        .init();
+
+    provider
+}
+
+/// Exports the spans still buffered by the provider.
+pub fn shutdown(provider: Option<TracerProvider>) {
+    if let Some(provider) = provider {
+        for result in provider.force_flush() {
+            if let Err(err) = result {
+                tracing::error!("Error on flush spans: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Builds a provider batching spans to `{endpoint}/v1/traces` over OTLP/HTTP.
+pub fn tracer_provider(endpoint: &str, service_name: &str) -> Result<TracerProvider, TraceError> {
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(format!("{}{}", endpoint.trim_end_matches('/'), OTLP_TRACES_PATH)),
+    )
+    .build_span_exporter()?;
+
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry::runtime::TokioCurrentThread)
+        .with_config(sdktrace::config().with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            service_name.to_string(),
+        )])))
+        .build())
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(key), HeaderValue::from_str(&value)) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Middleware opening a server span per request.
+///
+/// The span continues the trace from an incoming W3C `traceparent` header, and its own context
+/// is injected back into the response headers.
+pub struct ServerSpan;
+
+impl<S, B> Transform<S, ServiceRequest> for ServerSpan
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = ServerSpanMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ServerSpanMiddleware { service }))
+    }
+}
+
+pub struct ServerSpanMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for ServerSpanMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        let span = tracing::info_span!(
+            "HTTP request",
+            otel.name = %format!("{} {}", req.method(), route),
+            otel.kind = "server",
+            http.method = %req.method(),
+            http.route = %route,
+            // Without the query string, which carries emails, searches and filters.
+            http.target = %req.path(),
+            http.status_code = tracing::field::Empty,
+        );
+        span.set_parent(parent);
+
+        let fut = self.service.call(req).instrument(span.clone());
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            span.record("http.status_code", &res.status().as_u16());
+            global::get_text_map_propagator(|propagator| {
+                propagator.inject_context(&span.context(), &mut HeaderInjector(res.headers_mut()))
+            });
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web;
+    use actix_web::{App, HttpResponse};
+    use httpmock::prelude::*;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id};
+    use tracing_subscriber::layer::Context;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[actix_rt::test]
+    async fn server_span_continues_incoming_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = App::new()
+            .wrap(ServerSpan)
+            .route("/v1/user", web::get().to(HttpResponse::Ok));
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/v1/user")
+            .insert_header(("traceparent", TRACEPARENT))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+
+        let traceparent = res.headers().get("traceparent").unwrap().to_str().unwrap();
+        assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+        assert_ne!(traceparent, TRACEPARENT);
+    }
+
+    /// Records the `http.target` of every span.
+    #[derive(Clone, Default)]
+    struct Targets(Arc<Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Targets {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            struct Visitor<'a>(&'a mut Vec<String>);
+            impl Visit for Visitor<'_> {
+                fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                    if field.name() == "http.target" {
+                        self.0.push(format!("{:?}", value));
+                    }
+                }
+            }
+            attrs.record(&mut Visitor(&mut self.0.lock().unwrap()));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn server_span_target_has_no_query_string() {
+        let targets = Targets::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(targets.clone()));
+
+        let app = App::new()
+            .wrap(ServerSpan)
+            .route("/v1/user", web::get().to(HttpResponse::Ok));
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::get().uri("/v1/user?email=teste@teste.com").to_request();
+        actix_web::test::call_service(&app, req).await;
+
+        assert_eq!(*targets.0.lock().unwrap(), vec!["/v1/user".to_string()]);
+    }
+
+    #[actix_rt::test]
+    async fn server_span_injects_nothing_without_exporter() {
+        let app = App::new()
+            .wrap(ServerSpan)
+            .route("/v1/user", web::get().to(HttpResponse::Ok));
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::get().uri("/v1/user").to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert!(res.headers().get("traceparent").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn tracer_provider_exports_spans_to_collector() {
+        let collector = MockServer::start();
+        let m = collector.mock(|when, then| {
+            when.method(POST).path(OTLP_TRACES_PATH);
+            then.status(200);
+        });
+
+        let provider = tracer_provider(&collector.base_url(), "my-api-test").unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("repository", db.statement = "SELECT 1").entered();
+        });
+        provider.force_flush();
+
+        m.assert();
+    }
+}
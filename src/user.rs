@@ -1,25 +1,60 @@
 use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+use crate::custom_data::{self, CustomData};
+use crate::email::EmailRules;
+use crate::error::Error;
+use crate::redact::{Redacted, RedactedEmail};
+
+#[derive(Clone, Serialize, Deserialize, FromRow)]
 pub struct User {
     pub id: uuid::Uuid,
     pub email: String,
     pub name: String,
     pub birth_date: NaiveDate,
+    #[serde(default = "custom_data::empty")]
     pub custom_data: CustomData,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
-#[sqlx(type_name = "custom_data")]
-pub struct CustomData {
-    pub random: i32,
+impl User {
+    /// Checks the rules a user must satisfy before being stored.
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_fields(&self.email, &self.name, self.birth_date)
+    }
+}
+
+/// Rules shared by every way of writing a user.
+pub fn validate_fields(email: &str, name: &str, birth_date: NaiveDate) -> Result<(), Error> {
+    if name.trim().is_empty() {
+        return Err(Error::new("Name must not be empty".to_string(), 422).with_field("name".to_string()));
+    }
+    EmailRules::default().normalize(email)?;
+    if birth_date > Utc::today().naive_utc() {
+        return Err(Error::new("Birth date must not be in the future".to_string(), 422)
+            .with_field("birth_date".to_string()));
+    }
+    Ok(())
+}
+
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("email", &RedactedEmail(&self.email))
+            .field("name", &Redacted(&self.name))
+            .field("birth_date", &Redacted(&self.birth_date))
+            .field("custom_data", &Redacted(&self.custom_data))
+            .field("created_at", &self.created_at)
+            .field("updated_at", &self.updated_at)
+            .finish()
+    }
 }
 
-#[allow(dead_code)]
+#[cfg(test)]
 pub fn create_test_user(id: uuid::Uuid, name: String, birth_date_ymd: (i32, u32, u32)) -> User {
     let (year, month, day) = birth_date_ymd;
     User {
@@ -27,7 +62,7 @@ pub fn create_test_user(id: uuid::Uuid, name: String, birth_date_ymd: (i32, u32,
         name,
         email: "teste@teste.com".to_string(),
         birth_date: NaiveDate::from_ymd(year, month, day),
-        custom_data: CustomData { random: 1 },
+        custom_data: serde_json::json!({"random": 1}),
         created_at: Some(Utc::now()),
         updated_at: None,
     }
@@ -0,0 +1,269 @@
+use actix_web::web::Bytes;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::create_user::CreateUser;
+use crate::custom_data::CustomData;
+use crate::error::Error;
+use crate::user::{validate_fields, User};
+
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+/// Largest CSV upload accepted, by the synchronous and the background import alike.
+pub const IMPORT_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// Columns of exported users; `custom_data` is written as a JSON object.
+const COLUMNS: &[&str] = &["id", "email", "name", "birth_date", "custom_data", "created_at", "updated_at"];
+/// Columns an import must have; other columns are ignored except `custom_data` and `custom_data.*`.
+const IMPORT_COLUMNS: &[&str] = &["email", "name", "birth_date"];
+/// Prefix of import columns each holding a single attribute of `custom_data`.
+const CUSTOM_DATA_PREFIX: &str = "custom_data.";
+/// Leading characters spreadsheets evaluate as formulas.
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+#[derive(Deserialize)]
+struct ImportRow {
+    email: String,
+    name: String,
+    birth_date: String,
+    #[serde(default)]
+    custom_data: Option<String>,
+}
+
+/// Each data row of an upload by line, as a user to create or why it is invalid.
+pub type ImportRows = Vec<(u64, Result<CreateUser, Error>)>;
+
+/// Outcome of importing a CSV upload, with a result per data row.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub created: usize,
+    pub failed: usize,
+    pub rows: Vec<ImportRowResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportRowResult {
+    /// Line of the row in the uploaded file, the header being line 1.
+    pub line: u64,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
+impl ImportReport {
+    pub fn push(&mut self, line: u64, result: Result<Uuid, Error>) {
+        let row = match result {
+            Ok(id) => {
+                self.created += 1;
+                ImportRowResult { line, status: 201, id: Some(id), error: None }
+            }
+            Err(error) => {
+                self.failed += 1;
+                ImportRowResult { line, status: error.status, id: None, error: Some(error) }
+            }
+        };
+        self.rows.push(row);
+    }
+}
+
+pub fn header() -> Result<Bytes, Error> {
+    record(COLUMNS)
+}
+
+pub fn row(user: &User) -> Result<Bytes, Error> {
+    let optional_date = |date: Option<DateTime<Utc>>| date.map(|date| date.to_rfc3339()).unwrap_or_default();
+    record([
+        user.id.to_string(),
+        escape_formula(&user.email),
+        escape_formula(&user.name),
+        user.birth_date.to_string(),
+        user.custom_data.to_string(),
+        optional_date(user.created_at),
+        optional_date(user.updated_at),
+    ])
+}
+
+/// Parses an uploaded CSV into the user of each row, or why the row is invalid, by line.
+pub fn parse(data: &[u8]) -> Result<ImportRows, Error> {
+    Ok(rows(data)?.collect())
+}
+
+/// Lazily parses the rows of an uploaded CSV, checking its header up front.
+pub fn rows(data: &[u8]) -> Result<impl Iterator<Item = (u64, Result<CreateUser, Error>)> + '_, Error> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(data);
+    let headers = reader
+        .headers()
+        .map_err(|e| Error::new(format!("Invalid CSV header: {}", e), 400))?
+        .clone();
+    if let Some(missing) = IMPORT_COLUMNS.iter().find(|column| !headers.iter().any(|h| h == **column)) {
+        return Err(Error::new(format!("Missing CSV column {}", missing), 422).with_field(missing.to_string()));
+    }
+
+    let rows = reader.into_records().enumerate().map(move |(index, record)| {
+        let line = record
+            .as_ref()
+            .ok()
+            .and_then(|record| record.position())
+            .map(|position| position.line())
+            .unwrap_or(index as u64 + 2);
+        let user = record.map_err(|e| row_error(e, &headers)).and_then(|record| {
+            let row = record
+                .deserialize::<ImportRow>(Some(&headers))
+                .map_err(|e| row_error(e, &headers))?;
+            let custom_data = custom_data(row.custom_data.as_deref(), &headers, &record)?;
+            create_user(row, custom_data)
+        });
+        (line, user)
+    });
+    Ok(rows)
+}
+
+fn create_user(row: ImportRow, custom_data: CustomData) -> Result<CreateUser, Error> {
+    let email = unescape_formula(&row.email);
+    let name = unescape_formula(&row.name);
+    let birth_date = row.birth_date.parse::<NaiveDate>().map_err(|e| {
+        Error::new(format!("Invalid birth date: {}", e), 422).with_field("birth_date".to_string())
+    })?;
+    validate_fields(&email, &name, birth_date)?;
+    Ok(CreateUser {
+        email,
+        name,
+        birth_date,
+        custom_data,
+        created_at: None,
+        updated_at: None,
+    })
+}
+
+/// Object of the `custom_data` column, if any, with the value of each `custom_data.<key>`
+/// column set as `<key>`; values that are not JSON are kept as strings.
+fn custom_data(json: Option<&str>, headers: &csv::StringRecord, record: &csv::StringRecord) -> Result<CustomData, Error> {
+    let mut custom_data = match json.filter(|json| !json.is_empty()) {
+        Some(json) => match serde_json::from_str(json) {
+            Ok(Value::Object(object)) => object,
+            _ => {
+                return Err(Error::new("Custom data must be a JSON object".to_string(), 422)
+                    .with_field("custom_data".to_string()))
+            }
+        },
+        None => Map::new(),
+    };
+    for (column, value) in headers.iter().zip(record.iter()) {
+        if let Some(key) = column.strip_prefix(CUSTOM_DATA_PREFIX).filter(|_| !value.is_empty()) {
+            let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+            custom_data.insert(key.to_string(), value);
+        }
+    }
+    Ok(Value::Object(custom_data))
+}
+
+fn row_error(e: csv::Error, headers: &csv::StringRecord) -> Error {
+    let field = match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            err.field().and_then(|index| headers.get(index as usize)).map(str::to_string)
+        }
+        _ => None,
+    };
+    let error = Error::new(format!("Invalid CSV row: {}", e), 422);
+    match field {
+        Some(field) => error.with_field(field),
+        None => error,
+    }
+}
+
+fn record<I, T>(fields: I) -> Result<Bytes, Error>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(fields)
+        .map_err(|e| Error::new(format!("Error on write CSV: {}", e), 500))?;
+    writer
+        .into_inner()
+        .map(Bytes::from)
+        .map_err(|e| Error::new(format!("Error on write CSV: {}", e), 500))
+}
+
+/// Keeps spreadsheets from running a value as a formula by prefixing it with a quote.
+fn escape_formula(value: &str) -> String {
+    if value.starts_with(FORMULA_PREFIXES) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn unescape_formula(value: &str) -> String {
+    match value.strip_prefix('\'') {
+        Some(rest) if rest.starts_with(FORMULA_PREFIXES) => rest.to_string(),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::create_test_user;
+
+    #[test]
+    fn row_flattens_custom_data_and_escapes_formulas() {
+        let user = create_test_user(Uuid::new_v4(), "=HYPERLINK(\"x\")".to_string(), (1977, 3, 10));
+        let row = String::from_utf8(row(&user).unwrap().to_vec()).unwrap();
+        assert_eq!(
+            row,
+            format!(
+                "{},teste@teste.com,\"'=HYPERLINK(\"\"x\"\")\",1977-03-10,\"{{\"\"random\"\":1}}\",{},\n",
+                user.id,
+                user.created_at.unwrap().to_rfc3339()
+            )
+        );
+        assert_eq!(
+            String::from_utf8(header().unwrap().to_vec()).unwrap(),
+            "id,email,name,birth_date,custom_data,created_at,updated_at\n"
+        );
+    }
+
+    #[test]
+    fn parse_reports_each_row() {
+        let csv = "email,name,birth_date,custom_data,custom_data.random,ignored\n\
+                   teste@teste.com,'=Nome,1977-03-10,\"{\"\"tags\"\":[\"\"a\"\"]}\",1,x\n\
+                   invalid,Nome,1977-03-10,,1,x\n\
+                   teste@teste.com,Nome,10/03/1977,,1,x\n\
+                   teste@teste.com,Nome,1977-03-10,[1],um,x\n";
+        let rows = parse(csv.as_bytes()).unwrap();
+
+        assert_eq!(rows.len(), 4);
+        let (line, user) = &rows[0];
+        assert_eq!(*line, 2);
+        let user = user.as_ref().unwrap();
+        assert_eq!(user.name, "=Nome");
+        assert_eq!(user.custom_data, serde_json::json!({"tags": ["a"], "random": 1}));
+        let (line, error) = &rows[1];
+        assert_eq!(*line, 3);
+        assert_eq!(error.as_ref().unwrap_err().field.as_deref(), Some("email"));
+        let (line, error) = &rows[2];
+        assert_eq!(*line, 4);
+        assert_eq!(error.as_ref().unwrap_err().field.as_deref(), Some("birth_date"));
+        let (_, error) = &rows[3];
+        assert_eq!(error.as_ref().unwrap_err().field.as_deref(), Some("custom_data"));
+    }
+
+    #[test]
+    fn parse_keeps_non_json_attributes_as_strings() {
+        let rows = parse(b"email,name,birth_date,custom_data.random\nteste@teste.com,Nome,1977-03-10,um\n").unwrap();
+        let user = rows[0].1.as_ref().unwrap();
+        assert_eq!(user.custom_data, serde_json::json!({"random": "um"}));
+    }
+
+    #[test]
+    fn parse_requires_import_columns() {
+        let err = parse(b"email,name\n").unwrap_err();
+        assert_eq!(err.status, 422);
+        assert_eq!(err.field.as_deref(), Some("birth_date"));
+    }
+}
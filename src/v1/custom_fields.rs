@@ -0,0 +1,169 @@
+use crate::custom_field::CustomField;
+use crate::error::Error;
+use crate::repository::Repository;
+use crate::v1::users::path_config_handler;
+use actix_web::http::header::LOCATION;
+use actix_web::web::{self, PathConfig, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse};
+
+const PATH: &str = "/admin/custom_fields";
+const CUSTOM_FIELD_ROUTE: &str = "custom_field";
+
+pub fn service<R: Repository>(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope(PATH)
+            .app_data(PathConfig::default().error_handler(path_config_handler))
+            .service(
+                web::resource("")
+                    .route(web::get().to(get_all::<R>))
+                    .route(web::post().to(post::<R>)),
+            )
+            .service(
+                web::resource("/{name}")
+                    .name(CUSTOM_FIELD_ROUTE)
+                    .route(web::get().to(get::<R>))
+                    .route(web::put().to(put::<R>))
+                    .route(web::delete().to(delete::<R>)),
+            ),
+    );
+}
+
+async fn get_all<R: Repository>(repo: web::Data<R>) -> HttpResponse {
+    match repo.list_custom_fields().await {
+        Ok(fields) => HttpResponse::Ok().json(fields),
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+async fn get<R: Repository>(name: web::Path<String>, repo: web::Data<R>) -> HttpResponse {
+    match repo.get_custom_field(&name).await {
+        Ok(field) => HttpResponse::Ok().json(field),
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+async fn post<R: Repository>(field: web::Json<CustomField>, req: HttpRequest, repo: web::Data<R>) -> HttpResponse {
+    if let Err(err) = field.validate() {
+        return HttpResponse::build(err.status_code()).json(err);
+    }
+
+    match repo.create_custom_field(&field).await {
+        Ok(field) => {
+            let mut res = HttpResponse::Created();
+            match req.url_for(CUSTOM_FIELD_ROUTE, [&field.name]) {
+                Ok(url) => {
+                    res.insert_header((LOCATION, url.path()));
+                }
+                Err(e) => tracing::error!("Error on build location for custom field {}: {}", field.name, e),
+            }
+            res.json(field)
+        }
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+/// Replaces the definition of an existing field; the name may be omitted from the body.
+async fn put<R: Repository>(name: web::Path<String>, field: web::Json<CustomField>, repo: web::Data<R>) -> HttpResponse {
+    let mut field = field.into_inner();
+    if field.name.is_empty() {
+        field.name = name.into_inner();
+    } else if field.name != *name {
+        let err = Error::new("Body name does not match the path".to_string(), 400).with_field("name".to_string());
+        return HttpResponse::BadRequest().json(err);
+    }
+    if let Err(err) = field.validate() {
+        return HttpResponse::build(err.status_code()).json(err);
+    }
+
+    match repo.update_custom_field(&field).await {
+        Ok(field) => HttpResponse::Ok().json(field),
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+async fn delete<R: Repository>(name: web::Path<String>, repo: web::Data<R>) -> HttpResponse {
+    match repo.delete_custom_field(&name).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MockRepository;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use serde_json::json;
+
+    fn plan() -> CustomField {
+        serde_json::from_value(json!({"name": "plan", "type": "string", "enum": ["free", "pro"], "indexed": true}))
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn post_creates_the_field() {
+        let mut repo = MockRepository::default();
+        repo.expect_create_custom_field()
+            .withf(|field| field == &plan())
+            .times(1)
+            .returning(|field| Ok(field.clone()));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(repo))
+                .configure(crate::v1::service::<MockRepository>),
+        )
+        .await;
+
+        let req = TestRequest::post().uri("/v1/admin/custom_fields").set_json(plan()).to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(LOCATION).unwrap(), "/v1/admin/custom_fields/plan");
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["enum"], json!(["free", "pro"]));
+    }
+
+    #[actix_rt::test]
+    async fn post_rejects_invalid_definitions() {
+        let mut repo = MockRepository::default();
+        repo.expect_create_custom_field().never();
+
+        let field = CustomField { default: Some(json!("gold")), ..plan() };
+        let req = TestRequest::default().to_http_request();
+        let result = post(web::Json(field), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn put_takes_the_name_from_the_path() {
+        let mut repo = MockRepository::default();
+        repo.expect_update_custom_field()
+            .withf(|field| field.name == "plan")
+            .times(1)
+            .returning(|field| Ok(field.clone()));
+
+        let field = CustomField { name: String::new(), ..plan() };
+        let result = put(web::Path::from("plan".to_string()), web::Json(field), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn put_with_mismatched_name() {
+        let mut repo = MockRepository::default();
+        repo.expect_update_custom_field().never();
+
+        let result = put(web::Path::from("other".to_string()), web::Json(plan()), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn delete_not_found() {
+        let mut repo = MockRepository::default();
+        repo.expect_delete_custom_field()
+            .returning(|_name| Err(Error::new("This custom field does not exist".to_string(), 404)));
+
+        let result = delete(web::Path::from("plan".to_string()), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,305 @@
+use std::cmp::Reverse;
+use std::ops::{Deref, DerefMut};
+
+use actix_web::body::BoxBody;
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::http::header::{Accept, Header, Quality, VARY};
+use actix_web::middleware::DefaultHeaders;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// Other names MessagePack goes by.
+const MESSAGE_PACK_ALIASES: &[&str] = &["application/x-msgpack", "application/vnd.msgpack"];
+/// Largest request body decoded, the default limit of JSON bodies before other formats.
+pub const BODY_MAX_BYTES: usize = 2 * 1024 * 1024;
+
+/// Encoding of request and response bodies. MessagePack and CBOR carry the same values as JSON,
+/// ids and dates included as strings: bodies go through `serde_json::Value` in every format, as
+/// shaped responses already do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    /// In order of preference when the client accepts any of them.
+    const ALL: [Format; 3] = [Format::Json, Format::MessagePack, Format::Cbor];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    fn from_mime(essence: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.content_type() == essence)
+            .or_else(|| MESSAGE_PACK_ALIASES.contains(&essence).then_some(Format::MessagePack))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::MessagePack => "MessagePack",
+            Format::Cbor => "CBOR",
+        }
+    }
+
+    pub fn supported() -> String {
+        Self::ALL.iter().map(|format| format.content_type()).collect::<Vec<_>>().join(", ")
+    }
+
+    /// The format of the response: the one the client prefers in `Accept`, JSON without a
+    /// preference.
+    pub fn negotiate(req: &HttpRequest) -> Result<Self, Error> {
+        let mut ranges = match Accept::parse(req) {
+            Ok(Accept(ranges)) if !ranges.is_empty() => ranges,
+            // Missing or malformed.
+            _ => return Ok(Format::Json),
+        };
+        ranges.sort_by_key(|range| Reverse(range.quality));
+        let (acceptable, refused): (Vec<_>, Vec<_>) =
+            ranges.iter().partition(|range| range.quality > Quality::ZERO);
+        let refused: Vec<Format> =
+            refused.iter().filter_map(|range| Self::from_mime(range.item.essence_str())).collect();
+
+        acceptable
+            .iter()
+            .find_map(|range| match range.item.essence_str() {
+                "*/*" | "application/*" => Self::ALL.iter().copied().find(|format| !refused.contains(format)),
+                essence => Self::from_mime(essence),
+            })
+            .ok_or_else(|| Error::new(format!("Acceptable types are {}", Self::supported()), 406))
+    }
+
+    /// The format of the request body, JSON when it has no `Content-Type`.
+    pub fn from_content_type(req: &HttpRequest) -> Result<Self, Error> {
+        match req.content_type() {
+            "" => Ok(Format::Json),
+            essence => Self::from_mime(essence)
+                .ok_or_else(|| Error::new(format!("Content-Type must be one of {}", Self::supported()), 415)),
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, Error> {
+        let body = match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Format::MessagePack => serde_json::to_value(value)
+                .map_err(|e| e.to_string())
+                .and_then(|value| rmp_serde::to_vec_named(&value).map_err(|e| e.to_string())),
+            Format::Cbor => serde_json::to_value(value).map_err(|e| e.to_string()).and_then(|value| {
+                let mut body = Vec::new();
+                ciborium::ser::into_writer(&value, &mut body).map(|_| body).map_err(|e| e.to_string())
+            }),
+        };
+        body.map_err(|e| {
+            tracing::error!("Error on serialize response as {}: {}", self.name(), e);
+            Error::new("Error on serialize response".to_string(), 500)
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, Error> {
+        let value = match self {
+            Format::Json => serde_json::from_slice(body).map_err(|e| e.to_string()),
+            Format::MessagePack => rmp_serde::from_slice::<serde_json::Value>(body)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string())),
+            Format::Cbor => ciborium::de::from_reader::<serde_json::Value, _>(body)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string())),
+        };
+        value.map_err(|e| Error::new(format!("Invalid {}: {}", self.name(), e), 400))
+    }
+}
+
+/// Extracts the negotiated response format, rejecting the request with 406 before the handler
+/// runs when none is acceptable.
+impl FromRequest for Format {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Format::negotiate(req).map_err(rejection))
+    }
+}
+
+/// Marks every response of a scope negotiating its format, 304s and errors included, as varying
+/// with `Accept`, so shared caches keep a copy per format.
+pub fn vary_accept() -> DefaultHeaders {
+    DefaultHeaders::new().add((VARY, "Accept"))
+}
+
+/// Sends a value in the negotiated format, in place of `HttpResponseBuilder::json`.
+pub trait EncodedResponse {
+    fn encoded<T: Serialize + ?Sized>(&mut self, format: Format, value: &T) -> HttpResponse;
+}
+
+impl EncodedResponse for HttpResponseBuilder {
+    fn encoded<T: Serialize + ?Sized>(&mut self, format: Format, value: &T) -> HttpResponse {
+        match format.encode(value) {
+            Ok(body) => self.content_type(format.content_type()).body(body),
+            Err(err) => HttpResponse::build(err.status_code()).json(err),
+        }
+    }
+}
+
+/// A request body decoded according to its `Content-Type`, or a response body encoded as the
+/// client prefers; the counterpart of `web::Json` for every [`Format`].
+#[derive(Debug)]
+pub struct Encoded<T>(pub T);
+
+impl<T> Encoded<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Encoded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Encoded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// Bodies are read up to the `web::PayloadConfig` limit, see [`BODY_MAX_BYTES`].
+impl<T: DeserializeOwned + 'static> FromRequest for Encoded<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let format = Format::from_content_type(req);
+        let body = web::Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let format = format.map_err(rejection)?;
+            let body = body.await.map_err(|e| {
+                let status = e.as_response_error().status_code();
+                rejection(Error::new(format!("Invalid body: {}", e), status.as_u16()))
+            })?;
+            format.decode(&body).map(Encoded).map_err(rejection)
+        })
+    }
+}
+
+impl<T: Serialize> Responder for Encoded<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        match Format::negotiate(req) {
+            Ok(format) => HttpResponse::Ok().encoded(format, &self.0),
+            Err(err) => HttpResponse::build(err.status_code()).json(err),
+        }
+    }
+}
+
+/// Errors before the format is known are sent as JSON.
+fn rejection(err: Error) -> actix_web::Error {
+    let res = HttpResponse::build(err.status_code()).json(&err);
+    InternalError::from_response(err.message, res).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        id: uuid::Uuid,
+        name: String,
+        custom_data: serde_json::Value,
+    }
+
+    fn accepting(accept: &str) -> Result<Format, Error> {
+        Format::negotiate(&TestRequest::default().insert_header(("accept", accept)).to_http_request())
+    }
+
+    #[test]
+    fn negotiate_follows_the_client_preference() {
+        assert_eq!(Format::negotiate(&TestRequest::default().to_http_request()).unwrap(), Format::Json);
+        assert_eq!(accepting("*/*").unwrap(), Format::Json);
+        assert_eq!(accepting("application/json;q=0.5, application/cbor").unwrap(), Format::Cbor);
+        assert_eq!(accepting("text/html, application/x-msgpack;q=0.9").unwrap(), Format::MessagePack);
+        assert_eq!(accepting("application/json;q=0, */*;q=0.1").unwrap(), Format::MessagePack);
+
+        let err = accepting("text/csv").unwrap_err();
+        assert_eq!(err.status, 406);
+        assert_eq!(err.message, "Acceptable types are application/json, application/msgpack, application/cbor");
+        assert_eq!(accepting("application/cbor;q=0").unwrap_err().status, 406);
+    }
+
+    #[test]
+    fn from_content_type_defaults_to_json() {
+        let content_type = |value: &str| {
+            Format::from_content_type(&TestRequest::default().insert_header(("content-type", value)).to_http_request())
+        };
+        assert_eq!(Format::from_content_type(&TestRequest::default().to_http_request()).unwrap(), Format::Json);
+        assert_eq!(content_type("application/json; charset=utf-8").unwrap(), Format::Json);
+        assert_eq!(content_type("application/vnd.msgpack").unwrap(), Format::MessagePack);
+        assert_eq!(content_type("text/plain").unwrap_err().status, 415);
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let sample = Sample {
+            id: uuid::Uuid::new_v4(),
+            name: "Ana".to_string(),
+            custom_data: json!({"team": "core", "level": 3}),
+        };
+        for format in Format::ALL {
+            let body = format.encode(&sample).unwrap();
+            assert_eq!(format.decode::<Sample>(&body).unwrap(), sample, "{:?}", format);
+        }
+        // Ids are strings in every format: a MessagePack str8 of 36 bytes.
+        let body = Format::MessagePack.encode(&sample).unwrap();
+        assert!(body.windows(5).any(|window| window == [0xa2, b'i', b'd', 0xd9, 36]));
+
+        let err = Format::Cbor.decode::<Sample>(b"{}").unwrap_err();
+        assert_eq!(err.status, 400);
+        assert!(err.message.starts_with("Invalid CBOR"));
+    }
+
+    #[actix_rt::test]
+    async fn encoded_extracts_and_responds_in_the_negotiated_format() {
+        let sample = json!({"name": "Ana"});
+        let (req, mut payload) = TestRequest::post()
+            .insert_header(("content-type", "application/msgpack"))
+            .insert_header(("accept", "application/cbor"))
+            .set_payload(Format::MessagePack.encode(&sample).unwrap())
+            .to_http_parts();
+        let body = Encoded::<serde_json::Value>::from_request(&req, &mut payload).await.unwrap();
+        assert_eq!(*body, sample);
+
+        let res = body.respond_to(&req);
+        assert_eq!(res.headers().get("content-type").unwrap(), "application/cbor");
+        let bytes = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(Format::Cbor.decode::<serde_json::Value>(&bytes).unwrap(), sample);
+
+        let (req, mut payload) = TestRequest::post()
+            .insert_header(("content-type", "text/plain"))
+            .set_payload("Ana")
+            .to_http_parts();
+        let err = Encoded::<serde_json::Value>::from_request(&req, &mut payload).await.unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::error::Error;
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Fields of a user `fields=` may select.
+const USER_FIELDS: &[&str] = &["id", "name", "email", "birth_date", "custom_data", "created_at", "updated_at"];
+/// Kept in every shaped user, so clients can still identify and navigate it.
+const ALWAYS_INCLUDED: &[&str] = &["id", "_links"];
+/// Related resources `expand=` may embed. None exist yet: when one is added, register its name
+/// here, keep the parsed names in [`ResponseShape`] and embed them in [`ResponseShape::apply`].
+const EXPANSIONS: &[&str] = &[];
+
+/// Query parameters shaping user responses, e.g. `?fields=id,name`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ShapeQuery {
+    /// Comma separated fields to return instead of the whole user.
+    pub fields: Option<String>,
+    /// Comma separated related resources to embed.
+    pub expand: Option<String>,
+}
+
+/// How users are serialized in a response: every field, or only the selected ones. Every column
+/// is still read from the database.
+#[derive(Debug, Default)]
+pub struct ResponseShape {
+    fields: Option<Vec<&'static str>>,
+}
+
+impl ResponseShape {
+    /// Reads the shape from a query string, rejecting unknown fields and expansions.
+    pub fn from_query(query: &str) -> Result<Self, Error> {
+        let query = web::Query::<ShapeQuery>::from_query(query)
+            .map_err(|e| Error::new(format!("Invalid query string: {}", e), 400))?;
+        let fields = match &query.fields {
+            Some(fields) => Some(names("fields", fields, USER_FIELDS)?),
+            None => None,
+        };
+        if let Some(expand) = &query.expand {
+            names("expand", expand, EXPANSIONS)?;
+        }
+        Ok(Self { fields })
+    }
+
+    /// Serializes a user resource, keeping only the selected fields.
+    pub fn apply<T: Serialize>(&self, resource: &T) -> Result<Value, Error> {
+        let mut value = serde_json::to_value(resource).map_err(|e| {
+            tracing::error!("Error on serialize user: {}", e);
+            Error::new("Error on serialize user".to_string(), 500)
+        })?;
+        if let (Some(fields), Value::Object(object)) = (&self.fields, &mut value) {
+            object.retain(|key, _| fields.contains(&key.as_str()) || ALWAYS_INCLUDED.contains(&key.as_str()));
+        }
+        Ok(value)
+    }
+
+    pub fn apply_all<T: Serialize>(&self, resources: &[T]) -> Result<Vec<Value>, Error> {
+        resources.iter().map(|resource| self.apply(resource)).collect()
+    }
+}
+
+/// Parses a comma separated list of names from `allowed`.
+fn names(parameter: &str, list: &str, allowed: &[&'static str]) -> Result<Vec<&'static str>, Error> {
+    list.split(',')
+        .map(str::trim)
+        .map(|name| {
+            allowed.iter().find(|allowed| **allowed == name).copied().ok_or_else(|| {
+                let message = if allowed.is_empty() {
+                    format!("Unknown {} {}, none is supported", parameter, name)
+                } else {
+                    format!("Unknown {} {}, expected one of {}", parameter, name, allowed.join(", "))
+                };
+                Error::new(message, 400).with_field(parameter.to_string())
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn user() -> Value {
+        json!({"id": "1", "name": "Ana", "email": "ana@teste.com", "_links": {"self": {"href": "/v1/user/1"}}})
+    }
+
+    #[test]
+    fn apply_keeps_selected_fields_id_and_links() {
+        let shape = ResponseShape::from_query("fields=name&page=1").unwrap();
+        assert_eq!(
+            shape.apply(&user()).unwrap(),
+            json!({"id": "1", "name": "Ana", "_links": {"self": {"href": "/v1/user/1"}}})
+        );
+    }
+
+    #[test]
+    fn without_fields_the_whole_user_is_kept() {
+        let shape = ResponseShape::from_query("email=ana@teste.com").unwrap();
+        assert_eq!(shape.apply_all(&[user()]).unwrap(), vec![user()]);
+    }
+
+    #[test]
+    fn from_query_rejects_unknown_names() {
+        let err = ResponseShape::from_query("fields=id,password").unwrap_err();
+        assert_eq!((err.status, err.field.as_deref()), (400, Some("fields")));
+        assert!(err.message.starts_with("Unknown fields password, expected one of id, name"));
+        assert!(ResponseShape::from_query("fields=").is_err());
+
+        let err = ResponseShape::from_query("expand=company").unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("expand"));
+    }
+}
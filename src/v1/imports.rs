@@ -0,0 +1,189 @@
+use crate::error::Error;
+use crate::import_job::ImportJob;
+use crate::repository::Repository;
+use crate::user_csv::{self, CSV_CONTENT_TYPE, IMPORT_MAX_BYTES};
+use crate::v1::users::path_config_handler;
+use actix_web::http::header::LOCATION;
+use actix_web::web::{self, PathConfig, ServiceConfig};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use uuid::Uuid;
+
+const PATH: &str = "/imports";
+const IMPORT_ROUTE: &str = "import";
+
+pub fn service<R: Repository>(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope(PATH)
+            .app_data(PathConfig::default().error_handler(path_config_handler))
+            .service(
+                web::resource("")
+                    .app_data(web::PayloadConfig::new(IMPORT_MAX_BYTES))
+                    .route(web::post().to(post::<R>)),
+            )
+            .service(
+                web::resource("/{import_id}")
+                    .name(IMPORT_ROUTE)
+                    .route(web::get().to(get::<R>)),
+            )
+            .service(web::resource("/{import_id}/cancel").route(web::post().to(cancel::<R>))),
+    );
+}
+
+async fn post<R: Repository>(req: HttpRequest, body: web::Bytes, repo: web::Data<R>) -> HttpResponse {
+    if req.content_type() != CSV_CONTENT_TYPE {
+        let err = Error::new(format!("Content-Type must be {}", CSV_CONTENT_TYPE), 415);
+        return HttpResponse::UnsupportedMediaType().json(err);
+    }
+    // Parsing every row is too slow for the async workers on large uploads.
+    let data = body.clone();
+    let total_rows = match web::block(move || user_csv::rows(&data).map(|rows| rows.count() as i64)).await {
+        Ok(Ok(total_rows)) => total_rows,
+        Ok(Err(err)) => return HttpResponse::build(err.status_code()).json(err),
+        Err(e) => {
+            tracing::error!("Error on count import rows: {}", e);
+            let err = Error::new("Error on count import rows".to_string(), 500);
+            return HttpResponse::build(err.status_code()).json(err);
+        }
+    };
+
+    match repo.create_import(&body, total_rows).await {
+        Ok(job) => accepted(&req, job),
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+async fn get<R: Repository>(import_id: web::Path<Uuid>, repo: web::Data<R>) -> HttpResponse {
+    match repo.get_import(&import_id).await {
+        Ok(job) => HttpResponse::Ok().json(job),
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+async fn cancel<R: Repository>(import_id: web::Path<Uuid>, repo: web::Data<R>) -> HttpResponse {
+    match repo.cancel_import(&import_id).await {
+        Ok(job) => HttpResponse::Ok().json(job),
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+fn accepted(req: &HttpRequest, job: ImportJob) -> HttpResponse {
+    let mut res = HttpResponse::Accepted();
+    match req.url_for(IMPORT_ROUTE, [job.id.to_string()]) {
+        Ok(url) => {
+            res.insert_header((LOCATION, url.path()));
+        }
+        Err(e) => tracing::error!("Error on build location for import {}: {}", job.id, e),
+    }
+    res.json(job)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import_job::ImportStatus;
+    use crate::repository::MockRepository;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use chrono::Utc;
+
+    const CSV: &str = "email,name,birth_date,custom_data.random\n\
+                       um@teste.com,Um,1977-03-10,1\n\
+                       dois@teste.com,Dois,1977-03-10,1\n";
+
+    fn job(status: ImportStatus) -> ImportJob {
+        ImportJob {
+            id: Uuid::new_v4(),
+            status,
+            total_rows: 2,
+            processed_rows: 0,
+            created_rows: 0,
+            failed_rows: 0,
+            errors: Vec::new(),
+            error: None,
+            created_at: Utc::now(),
+            updated_at: None,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn post_stores_the_file_and_returns_the_job_location() {
+        let mut repo = MockRepository::default();
+        repo.expect_create_import()
+            .withf(|data, total_rows| data == CSV.as_bytes() && *total_rows == 2)
+            .times(1)
+            .returning(|_data, _total_rows| Ok(job(ImportStatus::Pending)));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(repo))
+                .configure(crate::v1::service::<MockRepository>),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/v1/imports")
+            .insert_header(("content-type", CSV_CONTENT_TYPE))
+            .set_payload(CSV)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let location = res.headers().get(LOCATION).unwrap().to_str().unwrap().to_string();
+        assert!(location.starts_with("/v1/imports/"));
+
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["status"], "pending");
+        assert_eq!(location, format!("/v1/imports/{}", body["id"].as_str().unwrap()));
+    }
+
+    #[actix_rt::test]
+    async fn post_rejects_a_file_without_import_columns() {
+        let mut repo = MockRepository::default();
+        repo.expect_create_import().never();
+
+        let req = TestRequest::default()
+            .insert_header(("content-type", CSV_CONTENT_TYPE))
+            .to_http_request();
+        let result = post(req, web::Bytes::from_static(b"email,name\n"), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn post_with_unsupported_content_type() {
+        let mut repo = MockRepository::default();
+        repo.expect_create_import().never();
+
+        let req = TestRequest::default().to_http_request();
+        let result = post(req, web::Bytes::from_static(CSV.as_bytes()), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[actix_rt::test]
+    async fn get_not_found() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_import()
+            .returning(|_id| Err(Error::new("This import does not exist".to_string(), 404)));
+
+        let result = get(web::Path::from(Uuid::new_v4()), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn cancel_finished_import_conflicts() {
+        let mut repo = MockRepository::default();
+        repo.expect_cancel_import()
+            .returning(|_id| Err(Error::new("This import is already completed".to_string(), 409)));
+
+        let result = cancel(web::Path::from(Uuid::new_v4()), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_rt::test]
+    async fn cancel_with_success() {
+        let mut repo = MockRepository::default();
+        repo.expect_cancel_import()
+            .returning(|_id| Ok(job(ImportStatus::Cancelled)));
+
+        let result = cancel(web::Path::from(Uuid::new_v4()), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,109 @@
+use actix_web::error::UrlGenerationError;
+use actix_web::{web, HttpRequest};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::page::Page;
+use crate::user::User;
+
+/// Names of the routes links are built from, registered by [`super::users::service`].
+pub const USERS_ROUTE: &str = "users";
+pub const USER_ROUTE: &str = "user";
+
+#[derive(Debug, Serialize)]
+pub struct Link {
+    pub href: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserLinks {
+    #[serde(rename = "self")]
+    pub self_link: Link,
+    pub collection: Link,
+}
+
+/// Navigation through the pages of the user collection, keeping the other query parameters.
+#[derive(Debug, Serialize)]
+pub struct PageLinks {
+    #[serde(rename = "self")]
+    pub self_link: Link,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<Link>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<Link>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<Link>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<Link>,
+}
+
+impl PageLinks {
+    /// Links of `page` in a collection of `total` users; only `self` without a page.
+    pub fn new(req: &HttpRequest, page: Option<Page>, total: i64) -> Result<Self, UrlGenerationError> {
+        let page = match page {
+            Some(page) => page,
+            None => {
+                let href = match req.query_string() {
+                    "" => req.url_for_static(USERS_ROUTE)?.path().to_string(),
+                    query => format!("{}?{}", req.url_for_static(USERS_ROUTE)?.path(), query),
+                };
+                return Ok(Self { self_link: Link { href }, first: None, prev: None, next: None, last: None });
+            }
+        };
+        let link = |offset: i64| page_link(req, page.limit, offset);
+        let last_offset = (total - 1).max(0) / page.limit * page.limit;
+        Ok(Self {
+            self_link: link(page.offset)?,
+            first: Some(link(0)?),
+            prev: if page.offset > 0 { Some(link((page.offset - page.limit).max(0))?) } else { None },
+            next: if page.offset + page.limit < total { Some(link(page.offset + page.limit)?) } else { None },
+            last: Some(link(last_offset)?),
+        })
+    }
+}
+
+fn page_link(req: &HttpRequest, limit: i64, offset: i64) -> Result<Link, UrlGenerationError> {
+    let mut url = req.url_for_static(USERS_ROUTE)?;
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    url.query_pairs_mut()
+        .extend_pairs(pairs.iter().filter(|(key, _)| key != "limit" && key != "offset"))
+        .append_pair("limit", &limit.to_string())
+        .append_pair("offset", &offset.to_string());
+    Ok(Link { href: format!("{}?{}", url.path(), url.query().unwrap_or_default()) })
+}
+
+/// A user as returned by the API, with links to itself and its collection.
+#[derive(Debug, Serialize)]
+pub struct UserResource {
+    #[serde(flatten)]
+    pub user: User,
+    #[serde(rename = "_links", skip_serializing_if = "Option::is_none")]
+    pub links: Option<UserLinks>,
+}
+
+impl UserResource {
+    pub fn new(req: &HttpRequest, user: User) -> Self {
+        let links = user_links(req, &user.id)
+            .map_err(|e| tracing::error!("Error on build links for user {}: {}", user.id, e))
+            .ok();
+        Self { user, links }
+    }
+
+    pub fn list(req: &HttpRequest, users: Vec<User>) -> Vec<Self> {
+        users.into_iter().map(|user| Self::new(req, user)).collect()
+    }
+
+    /// Path of the user, used as the `Location` of created users.
+    pub fn location(&self) -> Option<&str> {
+        self.links.as_ref().map(|links| links.self_link.href.as_str())
+    }
+}
+
+fn user_links(req: &HttpRequest, user_id: &Uuid) -> Result<UserLinks, UrlGenerationError> {
+    Ok(UserLinks {
+        self_link: Link { href: req.url_for(USER_ROUTE, [user_id.to_string()])?.path().to_string() },
+        collection: Link { href: req.url_for_static(USERS_ROUTE)?.path().to_string() },
+    })
+}
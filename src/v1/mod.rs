@@ -1,8 +1,18 @@
+mod custom_fields;
+mod encoding;
+mod fields;
+mod imports;
+mod links;
 mod users;
 
 use crate::repository::Repository;
 use actix_web::web::{self, ServiceConfig};
 
 pub fn service<R: Repository>(cfg: &mut ServiceConfig) {
-    cfg.service(web::scope("/v1").configure(users::service::<R>));
+    cfg.service(
+        web::scope("/v1")
+            .configure(users::service::<R>)
+            .configure(imports::service::<R>)
+            .configure(custom_fields::service::<R>),
+    );
 }
@@ -1,70 +1,691 @@
+use crate::bulk::{self, BulkConfig, BulkMode, BulkOperation, BulkRequest, BulkResponse};
+use crate::cache::{self, CacheConfig, Validators};
 use crate::create_user::CreateUser;
+use crate::custom_data::{self, CustomData};
+use crate::custom_data_filter::{self, CustomDataFilter};
+use crate::error::Error;
+use crate::filter_expression::FilterExpression;
+use crate::page::{Page, PageQuery, Total};
+use crate::patch::{JSON_PATCH_CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE};
+use crate::patch_user::PatchUser;
 use crate::repository::Repository;
+use crate::search::{SearchHighlights, SearchQuery};
 use crate::user::User;
+use crate::user_csv::{self, ImportReport, CSV_CONTENT_TYPE, IMPORT_MAX_BYTES};
+use crate::v1::encoding::{self, Encoded, EncodedResponse, Format, BODY_MAX_BYTES};
+use crate::v1::fields::ResponseShape;
+use crate::v1::links::{PageLinks, UserResource, USERS_ROUTE, USER_ROUTE};
 use actix_web::error::PathError;
+use actix_web::http::header::{Accept, Header, HeaderName, HeaderValue, LINK, LOCATION};
 use actix_web::web::{PathConfig, ServiceConfig, self};
-use actix_web::{HttpRequest, HttpResponse};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use chrono::NaiveDate;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 const PATH: &str = "/user";
+const DEPRECATION_HEADER: &str = "deprecation";
+/// Size of a listed collection, for clients reading bare arrays.
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+/// Lines buffered between the database and a slow client before the export pauses.
+const EXPORT_BUFFER: usize = 64;
+/// Most ids looked up at once by `?ids=` or `POST /v1/user/_batch`.
+const MAX_IDS: usize = 1000;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UserListQuery {
+    pub email: Option<String>,
+    /// Comma separated ids to fetch in one call, at most [`MAX_IDS`].
+    pub ids: Option<String>,
+    /// Expression like `name ~ "ana" and birth_date < 1990-01-01`, see `FilterExpression`.
+    pub filter: Option<String>,
+}
+
+/// Body of `POST /v1/user/_batch`, for lists of ids too long for a query string.
+#[derive(Debug, Deserialize)]
+pub struct UserIds {
+    pub ids: Vec<Uuid>,
+}
+
+/// Users found by id, in the requested order, and the requested ids that do not exist.
+#[derive(Debug, Serialize)]
+pub struct UserBatch {
+    /// Users shaped by `fields=`, see [`ResponseShape`].
+    pub users: Vec<serde_json::Value>,
+    pub missing: Vec<Uuid>,
+}
+
+/// A user found by `GET /v1/user/search`.
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub user: UserResource,
+    pub rank: f32,
+    pub highlights: SearchHighlights,
+}
+
+/// Body of `GET /v1/user?envelope=true`: the users with the size of the collection and links
+/// to the other pages.
+#[derive(Debug, Serialize)]
+pub struct UserPage {
+    /// Users shaped by `fields=`, see [`ResponseShape`].
+    pub items: Vec<serde_json::Value>,
+    pub total: i64,
+    /// Whether `total` comes from table statistics, see `count=estimated`.
+    pub total_estimated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    #[serde(rename = "_links", skip_serializing_if = "Option::is_none")]
+    pub links: Option<PageLinks>,
+}
+
+/// How a list of users is rendered, read from the query string.
+#[derive(Debug, Default)]
+struct ListFormat {
+    shape: ResponseShape,
+    paging: PageQuery,
+}
+
+impl ListFormat {
+    fn from_query(query: &str) -> Result<Self, Error> {
+        Ok(Self { shape: ResponseShape::from_query(query)?, paging: PageQuery::from_query(query)? })
+    }
+}
+
+/// Body of `PUT /v1/user/{id}`; the id may be omitted but must match the path when sent.
+#[derive(Deserialize)]
+pub struct PutUser {
+    pub id: Option<Uuid>,
+    pub email: String,
+    pub name: String,
+    pub birth_date: NaiveDate,
+    #[serde(default = "custom_data::empty")]
+    pub custom_data: CustomData,
+}
 
 pub fn service<R: Repository>(cfg: &mut ServiceConfig) {
     cfg.service(
         web::scope(PATH)
+            .wrap(encoding::vary_accept())
             .app_data(PathConfig::default().error_handler(path_config_handler))
-            .route("", web::get().to(get_all::<R>))
-            .route("/{user_id}", web::get().to(get::<R>))
-            .route("", web::post().to(post::<R>))
-            .route("", web::put().to(put::<R>))
-            .route("/{user_id}", web::delete().to(delete::<R>)),
+            .app_data(web::PayloadConfig::new(BODY_MAX_BYTES))
+            .service(
+                web::resource("")
+                    .name(USERS_ROUTE)
+                    .route(web::get().to(get_all::<R>))
+                    .route(web::post().to(post::<R>))
+                    .route(web::put().to(put::<R>)),
+            )
+            .service(web::resource("/_bulk").route(web::post().to(bulk::<R>)))
+            .service(web::resource("/_batch").route(web::post().to(batch::<R>)))
+            .service(web::resource("/export").route(web::get().to(export::<R>)))
+            .service(web::resource("/search").route(web::get().to(search::<R>)))
+            .service(
+                web::resource("/import")
+                    .app_data(web::PayloadConfig::new(IMPORT_MAX_BYTES))
+                    .route(web::post().to(import::<R>)),
+            )
+            .service(
+                web::resource("/{user_id}")
+                    .name(USER_ROUTE)
+                    .route(web::get().to(get::<R>))
+                    .route(web::put().to(put_by_id::<R>))
+                    .route(web::patch().to(patch::<R>))
+                    .route(web::delete().to(delete::<R>)),
+            ),
     );
 }
 
-async fn get_all<R: Repository>(repo: web::Data<R>) -> HttpResponse {
+async fn get_all<R: Repository>(
+    query: web::Query<UserListQuery>,
+    req: HttpRequest,
+    repo: web::Data<R>,
+) -> HttpResponse {
+    let format = match list_format(&req) {
+        Ok(format) => format,
+        Err(err) => return HttpResponse::build(err.status_code()).json(err),
+    };
+    let custom_data_filters = match custom_data_filters(req.query_string()) {
+        Ok(filters) => filters,
+        Err(err) => return HttpResponse::BadRequest().encoded(format, &err),
+    };
+    let list = match ListFormat::from_query(req.query_string()) {
+        Ok(list) => list,
+        Err(err) => return HttpResponse::BadRequest().encoded(format, &err),
+    };
+    let page = match list.paging.page() {
+        Ok(page) => page,
+        Err(err) => return HttpResponse::BadRequest().encoded(format, &err),
+    };
+    let filters = [
+        query.ids.is_some(),
+        query.email.is_some(),
+        !custom_data_filters.is_empty(),
+        query.filter.is_some(),
+    ];
+    if filters.iter().filter(|filter| **filter).count() > 1 {
+        let err = Error::new("Filter by either email, ids, custom data or a filter expression".to_string(), 400);
+        return HttpResponse::BadRequest().encoded(format, &err);
+    }
+    if page.is_some() && filters.contains(&true) {
+        let err = Error::new("Pagination is only supported when listing all users".to_string(), 400);
+        return HttpResponse::BadRequest().encoded(format, &err);
+    }
+    if let Some(filter) = &query.filter {
+        let filter = match FilterExpression::parse(filter) {
+            Ok(filter) => filter,
+            Err(err) => return HttpResponse::BadRequest().encoded(format, &err),
+        };
+        return match repo.get_users_by_filter(&filter).await {
+            Ok(users) => users_response(&req, format, &list, users),
+            Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+        };
+    }
+    if let Some(ids) = &query.ids {
+        return match parse_ids(ids) {
+            Ok(ids) => get_by_ids(ids, &req, format, &list, repo.get_ref()).await,
+            Err(err) => HttpResponse::BadRequest().encoded(format, &err),
+        };
+    }
+    if let Some(email) = &query.email {
+        return match repo.get_user_by_email(email).await {
+            Ok(user) => users_response(&req, format, &list, vec![user]),
+            Err(err) if err.status == 404 => users_response(&req, format, &list, Vec::new()),
+            Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+        };
+    }
+    if !custom_data_filters.is_empty() {
+        return match repo.get_users_by_custom_data(&custom_data_filters).await {
+            Ok(users) => users_response(&req, format, &list, users),
+            Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+        };
+    }
+    if let Some(page) = page {
+        let users = match repo.get_page(&page).await {
+            Ok(users) => users,
+            Err(err) => return HttpResponse::build(err.status_code()).encoded(format, &err),
+        };
+        return match repo.count_users(list.paging.count).await {
+            // An estimate may lag behind the rows just read.
+            Ok(total) => {
+                let total = Total { count: total.count.max(page.offset + users.len() as i64), ..total };
+                page_response(&req, format, &list, users, total, Some(page))
+            }
+            Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+        };
+    }
+    if accepts_csv(&req) {
+        return stream_users(repo, CSV_CONTENT_TYPE, Some(user_csv::header()), user_csv::row);
+    }
+
     match repo.get_all().await {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::BadGateway().json(err),
+        Ok(users) => users_response(&req, format, &list, users),
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
     }
 }
 
-async fn get<R: Repository>(user_id: web::Path<Uuid>, repo: web::Data<R>) -> HttpResponse {
-    match repo.get_user(&user_id).await {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::NotFound().json(err),
+/// Users matching a free text query by name or email, best matches first.
+async fn search<R: Repository>(
+    query: web::Query<SearchQuery>,
+    format: Format,
+    req: HttpRequest,
+    repo: web::Data<R>,
+) -> HttpResponse {
+    if let Err(err) = query.validate() {
+        return HttpResponse::BadRequest().encoded(format, &err);
     }
+
+    match repo.search_users(&query).await {
+        Ok(hits) => {
+            let hits: Vec<SearchResult> = hits
+                .into_iter()
+                .map(|hit| SearchResult {
+                    user: UserResource::new(&req, hit.user),
+                    rank: hit.rank,
+                    highlights: hit.highlights,
+                })
+                .collect();
+            HttpResponse::Ok().encoded(format, &hits)
+        }
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+    }
+}
+
+async fn batch<R: Repository>(body: Encoded<UserIds>, req: HttpRequest, repo: web::Data<R>) -> HttpResponse {
+    match list_format(&req) {
+        Ok(format) => get_by_ids(body.into_inner().ids, &req, format, &ListFormat::default(), repo.get_ref()).await,
+        Err(err) => HttpResponse::build(err.status_code()).json(err),
+    }
+}
+
+async fn get_by_ids<R: Repository>(
+    mut ids: Vec<Uuid>,
+    req: &HttpRequest,
+    format: Format,
+    list: &ListFormat,
+    repo: &R,
+) -> HttpResponse {
+    // A CSV body has nowhere to list the missing ids.
+    if accepts_csv(req) {
+        let err = Error::new(format!("Lookups by id are sent as {}", Format::supported()), 406);
+        return HttpResponse::NotAcceptable().encoded(format, &err);
+    }
+    if ids.len() > MAX_IDS {
+        let err = Error::new(format!("At most {} ids are accepted per request", MAX_IDS), 413)
+            .with_field("ids".to_string());
+        return HttpResponse::PayloadTooLarge().encoded(format, &err);
+    }
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+
+    let mut found: HashMap<Uuid, User> = match repo.get_users(&ids).await {
+        Ok(users) => users.into_iter().map(|user| (user.id, user)).collect(),
+        Err(err) => return HttpResponse::build(err.status_code()).encoded(format, &err),
+    };
+    let (mut users, mut missing) = (Vec::new(), Vec::new());
+    for id in ids {
+        match found.remove(&id) {
+            Some(user) => users.push(user),
+            None => missing.push(id),
+        }
+    }
+    match list.shape.apply_all(&UserResource::list(req, users)) {
+        Ok(users) => HttpResponse::Ok().encoded(format, &UserBatch { users, missing }),
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+    }
+}
+
+/// Renders a whole list of users, see [`page_response`].
+fn users_response(req: &HttpRequest, format: Format, list: &ListFormat, users: Vec<User>) -> HttpResponse {
+    let total = Total::exact(users.len());
+    page_response(req, format, list, users, total, None)
+}
+
+/// Renders users as CSV when the client prefers it and in the negotiated format otherwise;
+/// `fields=` does not shape CSV. Users are a bare array unless `envelope=true`, with the total in
+/// `X-Total-Count` either way.
+fn page_response(
+    req: &HttpRequest,
+    format: Format,
+    list: &ListFormat,
+    users: Vec<User>,
+    total: Total,
+    page: Option<Page>,
+) -> HttpResponse {
+    if accepts_csv(req) {
+        let rows: Result<Vec<web::Bytes>, Error> =
+            std::iter::once(user_csv::header()).chain(users.iter().map(user_csv::row)).collect();
+        return match rows {
+            Ok(rows) => HttpResponse::Ok().content_type(CSV_CONTENT_TYPE).body(rows.concat()),
+            Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+        };
+    }
+
+    let items = match list.shape.apply_all(&UserResource::list(req, users)) {
+        Ok(items) => items,
+        Err(err) => return HttpResponse::build(err.status_code()).encoded(format, &err),
+    };
+    let body = if list.paging.envelope {
+        let links = PageLinks::new(req, page, total.count)
+            .map_err(|e| tracing::error!("Error on build links for users page: {}", e))
+            .ok();
+        format.encode(&UserPage {
+            items,
+            total: total.count,
+            total_estimated: total.estimated,
+            limit: page.map(|page| page.limit),
+            offset: page.map(|page| page.offset),
+            links,
+        })
+    } else {
+        format.encode(&items)
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(err) => return HttpResponse::build(err.status_code()).encoded(format, &err),
+    };
+    let mut res = HttpResponse::Ok();
+    res.content_type(format.content_type())
+        .insert_header((HeaderName::from_static(TOTAL_COUNT_HEADER), total.count));
+    let validators = Validators::weak(&body);
+    cache::conditional(req, res, body, validators, &CacheConfig::of(req).users)
+}
+
+fn accepts_csv(req: &HttpRequest) -> bool {
+    Accept::parse(req)
+        .map(|accept| accept.preference().essence_str() == CSV_CONTENT_TYPE)
+        .unwrap_or(false)
+}
+
+/// Format of lists, which may also be rendered as CSV; errors of CSV requests are sent as JSON.
+fn list_format(req: &HttpRequest) -> Result<Format, Error> {
+    if accepts_csv(req) {
+        return Ok(Format::Json);
+    }
+    Format::negotiate(req)
+}
+
+fn custom_data_filters(query: &str) -> Result<Vec<CustomDataFilter>, Error> {
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(query)
+        .map_err(|e| Error::new(format!("Invalid query string: {}", e), 400))?;
+    custom_data_filter::from_query(&pairs)
 }
 
-async fn post<R: Repository>(user: web::Json<CreateUser>, repo: web::Data<R>) -> HttpResponse {
+fn parse_ids(ids: &str) -> Result<Vec<Uuid>, Error> {
+    ids.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            Uuid::parse_str(id)
+                .map_err(|_| Error::new(format!("Invalid id {}", id), 400).with_field("ids".to_string()))
+        })
+        .collect()
+}
+
+async fn export<R: Repository>(repo: web::Data<R>) -> HttpResponse {
+    stream_users(repo, NDJSON_CONTENT_TYPE, None, ndjson_line)
+}
+
+/// Streams every user, encoded one at a time, pausing the database reads while the client is
+/// slower than them.
+fn stream_users<R: Repository>(
+    repo: web::Data<R>,
+    content_type: &str,
+    header: Option<Result<web::Bytes, Error>>,
+    encode: fn(&User) -> Result<web::Bytes, Error>,
+) -> HttpResponse {
+    let (mut lines, body) = futures::channel::mpsc::channel(EXPORT_BUFFER);
+    actix_web::rt::spawn(async move {
+        if let Some(header) = header {
+            if lines.send(header.map_err(export_error)).await.is_err() {
+                return;
+            }
+        }
+
+        let mut users = repo.export_users();
+        let mut exported = 0;
+        while let Some(user) = users.next().await {
+            let line = user.and_then(|user| {
+                exported += 1;
+                encode(&user)
+            });
+            let failed = line.is_err();
+            // The client went away or the export failed; the body ends either way.
+            if lines.send(line.map_err(export_error)).await.is_err() || failed {
+                tracing::warn!("Export of users stopped after {} users", exported);
+                return;
+            }
+        }
+        tracing::info!("Exported {} users", exported);
+    });
+
+    HttpResponse::Ok().content_type(content_type).streaming(body)
+}
+
+fn ndjson_line(user: &User) -> Result<web::Bytes, Error> {
+    let mut line = serde_json::to_vec(user)
+        .map_err(|e| Error::new(format!("Error on serialize user: {}", e), 500))?;
+    line.push(b'\n');
+    Ok(web::Bytes::from(line))
+}
+
+fn export_error(err: Error) -> actix_web::Error {
+    let status = err.status_code();
+    actix_web::error::InternalError::new(err.message, status).into()
+}
+
+async fn import<R: Repository>(
+    format: Format,
+    req: HttpRequest,
+    body: web::Bytes,
+    config: web::Data<BulkConfig>,
+    repo: web::Data<R>,
+) -> HttpResponse {
+    if req.content_type() != CSV_CONTENT_TYPE {
+        let err = Error::new(format!("Content-Type must be {}", CSV_CONTENT_TYPE), 415);
+        return HttpResponse::UnsupportedMediaType().encoded(format, &err);
+    }
+    let rows = match user_csv::parse(&body) {
+        Ok(rows) => rows,
+        Err(err) => return HttpResponse::build(err.status_code()).encoded(format, &err),
+    };
+
+    let mut report = ImportReport::default();
+    let mut valid = Vec::with_capacity(rows.len());
+    for (line, user) in rows {
+        match user {
+            Ok(user) => valid.push((line, user)),
+            Err(err) => report.push(line, Err(err)),
+        }
+    }
+    for chunk in valid.chunks(config.max_operations.max(1)) {
+        let operations: Vec<BulkOperation> = chunk
+            .iter()
+            .map(|(_, user)| BulkOperation::Create { user: user.clone() })
+            .collect();
+        match repo.bulk(&operations, BulkMode::BestEffort).await {
+            Ok(results) => {
+                for ((line, _), result) in chunk.iter().zip(results) {
+                    let created = match (result.user, result.error) {
+                        (Some(user), _) => Ok(user.id),
+                        (None, Some(err)) => Err(err),
+                        (None, None) => Err(Error::new("User was not created".to_string(), result.status)),
+                    };
+                    report.push(*line, created);
+                }
+            }
+            Err(err) => {
+                for (line, _) in chunk {
+                    report.push(*line, Err(Error::new(err.message.clone(), err.status)));
+                }
+            }
+        }
+    }
+
+    report.rows.sort_by_key(|row| row.line);
+    tracing::info!("Imported {} users, {} rows failed", report.created, report.failed);
+    HttpResponse::Ok().encoded(format, &report)
+}
+
+async fn get<R: Repository>(
+    user_id: web::Path<Uuid>,
+    format: Format,
+    req: HttpRequest,
+    repo: web::Data<R>,
+) -> HttpResponse {
+    let shape = match ResponseShape::from_query(req.query_string()) {
+        Ok(shape) => shape,
+        Err(err) => return HttpResponse::BadRequest().encoded(format, &err),
+    };
+    let user = match repo.get_user(&user_id).await {
+        Ok(user) => user,
+        Err(err) => return HttpResponse::build(err.status_code()).encoded(format, &err),
+    };
+    let last_modified = user.updated_at.or(user.created_at);
+    match shape.apply(&UserResource::new(&req, user)).and_then(|user| format.encode(&user)) {
+        Ok(body) => {
+            let mut res = HttpResponse::Ok();
+            res.content_type(format.content_type());
+            let validators = Validators::strong(&body, last_modified);
+            cache::conditional(&req, res, body, validators, &CacheConfig::of(&req).user)
+        }
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+    }
+}
+
+async fn post<R: Repository>(
+    user: Encoded<CreateUser>,
+    format: Format,
+    req: HttpRequest,
+    repo: web::Data<R>,
+) -> HttpResponse {
+    if let Err(err) = user.validate() {
+        return HttpResponse::build(err.status_code()).encoded(format, &err);
+    }
+
     match repo.create_user(&user).await {
-        Ok(user) => HttpResponse::Created().json(user),
-        Err(err) => HttpResponse::UnprocessableEntity().json(err),
+        Ok(user) => created(format, UserResource::new(&req, user)),
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+    }
+}
+
+/// Legacy update taking the id from the body, superseded by `PUT /v1/user/{id}`.
+async fn put<R: Repository>(user: Encoded<User>, format: Format, req: HttpRequest, repo: web::Data<R>) -> HttpResponse {
+    let updated = match user.validate() {
+        Ok(()) => repo.update_user(&user).await,
+        Err(err) => Err(err),
+    };
+    let mut res = match updated {
+        Ok(user) => HttpResponse::Ok().encoded(format, &UserResource::new(&req, user)),
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+    };
+
+    res.headers_mut()
+        .insert(HeaderName::from_static(DEPRECATION_HEADER), HeaderValue::from_static("true"));
+    if let Ok(successor) = req.url_for(USER_ROUTE, [user.id.to_string()]) {
+        let link = format!("<{}>; rel=\"successor-version\"", successor.path());
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            res.headers_mut().insert(LINK, link);
+        }
+    }
+    res
+}
+
+async fn put_by_id<R: Repository>(
+    user_id: web::Path<Uuid>,
+    user: Encoded<PutUser>,
+    format: Format,
+    req: HttpRequest,
+    repo: web::Data<R>,
+) -> HttpResponse {
+    let user = user.into_inner();
+    if user.id.is_some_and(|id| id != *user_id) {
+        let err = Error::new("User id does not match the path".to_string(), 400).with_field("id".to_string());
+        return HttpResponse::BadRequest().encoded(format, &err);
+    }
+
+    let user = User {
+        id: *user_id,
+        email: user.email,
+        name: user.name,
+        birth_date: user.birth_date,
+        custom_data: user.custom_data,
+        created_at: None,
+        updated_at: None,
+    };
+    if let Err(err) = user.validate() {
+        return HttpResponse::build(err.status_code()).encoded(format, &err);
+    }
+
+    match repo.upsert_user(&user).await {
+        Ok((user, true)) => created(format, UserResource::new(&req, user)),
+        Ok((user, false)) => HttpResponse::Ok().encoded(format, &UserResource::new(&req, user)),
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
     }
 }
 
-async fn put<R: Repository>(user: web::Json<User>, repo: web::Data<R>) -> HttpResponse {
-    match repo.update_user(&user).await {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::UnprocessableEntity().json(err),
+async fn patch<R: Repository>(
+    user_id: web::Path<Uuid>,
+    format: Format,
+    req: HttpRequest,
+    body: web::Bytes,
+    repo: web::Data<R>,
+) -> HttpResponse {
+    let apply = match req.content_type() {
+        MERGE_PATCH_CONTENT_TYPE => PatchUser::merge,
+        JSON_PATCH_CONTENT_TYPE => PatchUser::apply,
+        _ => {
+            let accepted = format!("{}, {}", MERGE_PATCH_CONTENT_TYPE, JSON_PATCH_CONTENT_TYPE);
+            let err = Error::new(format!("Content-Type must be one of {}", accepted), 415);
+            return HttpResponse::UnsupportedMediaType()
+                .insert_header(("Accept-Patch", accepted))
+                .encoded(format, &err);
+        }
+    };
+    let patch: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(patch) => patch,
+        Err(e) => return HttpResponse::BadRequest().encoded(format, &Error::new(format!("Invalid JSON: {}", e), 400)),
+    };
+
+    let user = match repo.get_user(&user_id).await {
+        Ok(user) => user,
+        Err(err) => return HttpResponse::build(err.status_code()).encoded(format, &err),
+    };
+    let changes = match apply(&user, &patch) {
+        Ok((_, changes)) if changes.is_empty() => {
+            return HttpResponse::Ok().encoded(format, &UserResource::new(&req, user));
+        }
+        Ok((_, changes)) => changes,
+        Err(err) => return HttpResponse::build(err.status_code()).encoded(format, &err),
+    };
+
+    match repo.patch_user(&user_id, &changes, user.updated_at).await {
+        Ok(user) => HttpResponse::Ok().encoded(format, &UserResource::new(&req, user)),
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
     }
 }
 
-async fn delete<R: Repository>(user_id: web::Path<Uuid>, repo: web::Data<R>) -> HttpResponse {
+async fn delete<R: Repository>(user_id: web::Path<Uuid>, format: Format, repo: web::Data<R>) -> HttpResponse {
     match repo.delete_user(&user_id).await {
         Ok(_) => HttpResponse::NoContent().finish(),
-        Err(err) => HttpResponse::NotFound().json(err),
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
+    }
+}
+
+async fn bulk<R: Repository>(
+    request: Encoded<BulkRequest>,
+    format: Format,
+    config: web::Data<BulkConfig>,
+    repo: web::Data<R>,
+) -> HttpResponse {
+    if request.operations.len() > config.max_operations {
+        let message = format!("At most {} operations are accepted per request", config.max_operations);
+        let err = Error::new(message, 413).with_field("operations".to_string());
+        return HttpResponse::PayloadTooLarge().encoded(format, &err);
+    }
+
+    let (valid, mut invalid) = bulk::split_invalid(&request.operations);
+    if request.mode == BulkMode::Atomic {
+        if let Some((index, err)) = invalid.drain(..).next() {
+            let err = bulk::operation_error(index, err);
+            return HttpResponse::build(err.status_code()).encoded(format, &err);
+        }
+    }
+
+    match repo.bulk(&valid, request.mode).await {
+        Ok(results) => {
+            let results = bulk::merge_failures(results, invalid);
+            HttpResponse::Ok().encoded(format, &BulkResponse { results })
+        }
+        Err(err) => HttpResponse::build(err.status_code()).encoded(format, &err),
     }
 }
 
-fn path_config_handler(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+fn created(format: Format, user: UserResource) -> HttpResponse {
+    let mut res = HttpResponse::Created();
+    if let Some(location) = user.location() {
+        res.insert_header((LOCATION, location));
+    }
+    res.encoded(format, &user)
+}
+
+pub(super) fn path_config_handler(err: PathError, _req: &HttpRequest) -> actix_web::Error {
     actix_web::error::ErrorBadRequest(err)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::create_user::{CreateUser, CustomData as OtherCustomData};
-    use crate::user::{create_test_user};
-    use crate::{repository::MockRepository, Error};
+    use crate::create_user::CreateUser;
+    use crate::user::create_test_user;
+    use serde_json::json;
+    use crate::bulk::{BulkItemResult, BulkMode, BulkOperation};
+    use crate::memory_repository::InMemoryRepository;
+    use crate::repository::MockRepository;
     use actix_web::http::StatusCode;
     use chrono::{NaiveDate, Utc};
 
@@ -76,7 +697,7 @@ mod tests {
             name,
             email: "teste@teste.com".to_string(),
             birth_date: NaiveDate::from_ymd(year, month, day),
-            custom_data: OtherCustomData { random: 1 },
+            custom_data: json!({"random": 1}),
             created_at: Some(Utc::now()),
             updated_at: None,
         }
@@ -92,7 +713,7 @@ mod tests {
             Ok(users)
         });
 
-        let result = get_all(web::Data::new(repo)).await;
+        let result = get_all(web::Query(UserListQuery::default()), test_request(), web::Data::new(repo)).await;
         assert_eq!(result.status(), StatusCode::OK);
     }
 
@@ -101,10 +722,147 @@ mod tests {
         let mut repo = MockRepository::default();
         repo.expect_get_all().returning(move || Err(Error::new("error".to_string(), 502)));
 
-        let result = get_all(web::Data::new(repo)).await;
+        let result = get_all(web::Query(UserListQuery::default()), test_request(), web::Data::new(repo)).await;
         assert_eq!(result.status(), StatusCode::BAD_GATEWAY);
     }
 
+    #[actix_rt::test]
+    async fn get_all_by_email_with_success() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_user_by_email()
+            .withf(|email| email == "Teste@Teste.com")
+            .returning(|_email| Ok(create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))));
+
+        let query = UserListQuery { email: Some("Teste@Teste.com".to_string()), ..UserListQuery::default() };
+        let result = get_all(web::Query(query), test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(result.into_body()).await.unwrap();
+        let users: Vec<User> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(users.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_by_custom_data() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_users_by_custom_data()
+            .withf(|filters| filters == [CustomDataFilter::parse("custom_data.plan", "pro").unwrap().unwrap()])
+            .times(1)
+            .returning(|_filters| Ok(vec![create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))]));
+
+        let req = actix_web::test::TestRequest::with_uri("/v1/user?custom_data.plan=pro&page=1").to_http_request();
+        let result = get_all(web::Query(UserListQuery::default()), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_by_filter_expression() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_users_by_filter()
+            .withf(|filter| matches!(filter, FilterExpression::And(..)))
+            .times(1)
+            .returning(|_filter| Ok(vec![create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))]));
+
+        let query = UserListQuery {
+            filter: Some(r#"name ~ "ana" and birth_date < 1990-01-01"#.to_string()),
+            ..UserListQuery::default()
+        };
+        let req = actix_web::test::TestRequest::default().to_http_request();
+        let result = get_all(web::Query(query), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_with_invalid_filter_expression() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_users_by_filter().never();
+
+        let query = UserListQuery { filter: Some("password = 1".to_string()), ..UserListQuery::default() };
+        let req = actix_web::test::TestRequest::default().to_http_request();
+        let result = get_all(web::Query(query), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+
+        let repo = MockRepository::default();
+        let query = UserListQuery {
+            filter: Some(r#"name = "ana""#.to_string()),
+            email: Some("a@b.com".to_string()),
+            ..UserListQuery::default()
+        };
+        let req = actix_web::test::TestRequest::default().to_http_request();
+        let result = get_all(web::Query(query), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn search_ranks_and_highlights_users() {
+        let user = |name: &str, email: &str| User {
+            email: email.to_string(),
+            ..create_test_user(uuid::Uuid::new_v4(), name.to_string(), (1977, 3, 10))
+        };
+        let repo = InMemoryRepository::with_users(vec![
+            user("Anabela Souza", "bela@teste.com"),
+            user("Carla Dias", "carla@teste.com"),
+        ]);
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(repo))
+                .configure(crate::v1::service::<InMemoryRepository>),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::get().uri("/v1/user/search?q=anabella").to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        let hits = body.as_array().unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0]["user"]["name"], "Anabela Souza");
+        assert!(hits[0]["user"]["_links"]["self"]["href"].is_string());
+        assert!(hits[0]["rank"].as_f64().unwrap() > 0.0);
+    }
+
+    #[actix_rt::test]
+    async fn search_without_words_is_rejected() {
+        let req = actix_web::test::TestRequest::default().to_http_request();
+        let query = SearchQuery { q: " -- ".to_string(), limit: None };
+        let result = search(web::Query(query), Format::Json, req, web::Data::new(InMemoryRepository::default())).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_by_custom_data_and_email_is_rejected() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_users_by_custom_data().never();
+        repo.expect_get_user_by_email().never();
+
+        let req = actix_web::test::TestRequest::with_uri("/v1/user?custom_data.plan=pro&email=a@b.com").to_http_request();
+        let query = UserListQuery { email: Some("a@b.com".to_string()), ..UserListQuery::default() };
+        let result = get_all(web::Query(query), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_with_unknown_filter_operator() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_users_by_custom_data().never();
+
+        let req = actix_web::test::TestRequest::with_uri("/v1/user?custom_data.score%5Bnear%5D=1").to_http_request();
+        let result = get_all(web::Query(UserListQuery::default()), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_by_unknown_email_is_empty() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_user_by_email()
+            .returning(|_email| Err(Error::new("error".to_string(), 404)));
+
+        let query = UserListQuery { email: Some("teste@teste.com".to_string()), ..UserListQuery::default() };
+        let result = get_all(web::Query(query), test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(result.into_body()).await.unwrap();
+        assert_eq!(body, "[]".as_bytes());
+    }
+
     #[actix_rt::test]
     async fn get_user_with_success() {
         let user_id = uuid::Uuid::new_v4();
@@ -115,21 +873,228 @@ mod tests {
             Ok(user)
         });
 
-        let result = get(web::Path::from(user_id), web::Data::new(repo)).await;
+        let result = get(web::Path::from(user_id), Format::Json, test_request(), web::Data::new(repo)).await;
 
         assert_eq!(result.status(), StatusCode::OK);
     }
 
+    #[actix_rt::test]
+    async fn get_user_with_sparse_fields() {
+        let user = create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10));
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(InMemoryRepository::with_users(vec![user.clone()])))
+                .configure(crate::v1::service::<InMemoryRepository>),
+        )
+        .await;
+
+        let uri = format!("/v1/user/{}?fields=name", user.id);
+        let req = actix_web::test::TestRequest::get().uri(&uri).to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        let mut keys: Vec<&str> = body.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["_links", "id", "name"]);
+
+        let req = actix_web::test::TestRequest::get().uri("/v1/user?fields=name,birth_date").to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body[0]["birth_date"], "1977-03-10");
+        assert!(body[0].get("email").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn get_user_with_unknown_fields() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_user().never();
+        repo.expect_get_all().never();
+
+        let req = actix_web::test::TestRequest::with_uri("/v1/user/x?fields=name,password").to_http_request();
+        let result = get(web::Path::from(uuid::Uuid::new_v4()), Format::Json, req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+
+        let repo = MockRepository::default();
+        let req = actix_web::test::TestRequest::with_uri("/v1/user?expand=company").to_http_request();
+        let result = get_all(web::Query(UserListQuery::default()), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_pages_with_envelope_and_total() {
+        let users: Vec<User> = (0..3)
+            .map(|i| User {
+                email: format!("user{}@teste.com", i),
+                ..create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))
+            })
+            .collect();
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(InMemoryRepository::with_users(users)))
+                .configure(crate::v1::service::<InMemoryRepository>),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::get().uri("/v1/user?envelope=true&limit=2&fields=id").to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(TOTAL_COUNT_HEADER).unwrap(), "3");
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["items"].as_array().unwrap().len(), 2);
+        assert_eq!((body["total"].clone(), body["total_estimated"].clone()), (json!(3), json!(false)));
+        assert_eq!(body["_links"]["next"]["href"], "/v1/user?envelope=true&fields=id&limit=2&offset=2");
+        assert_eq!(body["_links"]["last"]["href"], "/v1/user?envelope=true&fields=id&limit=2&offset=2");
+        assert!(body["_links"].get("prev").is_none());
+
+        let req = actix_web::test::TestRequest::get().uri("/v1/user?limit=2&offset=2").to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(TOTAL_COUNT_HEADER).unwrap(), "3");
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+
+        let uri = "/v1/user?limit=10&offset=9223372036854775807&envelope=true";
+        let req = actix_web::test::TestRequest::get().uri(uri).to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_rejects_pagination_of_filtered_users() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_user_by_email().never();
+
+        let query = UserListQuery { email: Some("a@b.com".to_string()), ..UserListQuery::default() };
+        let req = actix_web::test::TestRequest::with_uri("/v1/user?email=a@b.com&limit=10").to_http_request();
+        let result = get_all(web::Query(query), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+
+        let req = actix_web::test::TestRequest::with_uri("/v1/user?limit=0").to_http_request();
+        let result = get_all(web::Query(UserListQuery::default()), req, web::Data::new(MockRepository::default())).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn get_user_and_list_revalidate_with_etags() {
+        let user = create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10));
+        let config = CacheConfig { user: "private, max-age=60".to_string(), ..CacheConfig::default() };
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(InMemoryRepository::with_users(vec![user.clone()])))
+                .app_data(web::Data::new(config))
+                .configure(crate::v1::service::<InMemoryRepository>),
+        )
+        .await;
+
+        let uri = format!("/v1/user/{}", user.id);
+        let req = actix_web::test::TestRequest::get().uri(&uri).to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("cache-control").unwrap(), "private, max-age=60");
+        assert!(res.headers().contains_key("last-modified"));
+        let etag = res.headers().get("etag").unwrap().clone();
+        assert!(!etag.to_str().unwrap().starts_with("W/"));
+
+        let req = actix_web::test::TestRequest::get()
+            .uri(&uri)
+            .insert_header(("if-none-match", etag.clone()))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get("etag").unwrap(), etag);
+
+        // Another representation of the same user has another tag.
+        let req = actix_web::test::TestRequest::get()
+            .uri(&format!("{}?fields=name", uri))
+            .insert_header(("if-none-match", etag))
+            .to_request();
+        assert_eq!(actix_web::test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = actix_web::test::TestRequest::get().uri("/v1/user").to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.headers().get("cache-control").unwrap(), "no-cache");
+        let etag = res.headers().get("etag").unwrap().clone();
+        assert!(etag.to_str().unwrap().starts_with("W/"));
+        let req = actix_web::test::TestRequest::get().uri("/v1/user").insert_header(("if-none-match", etag)).to_request();
+        assert_eq!(actix_web::test::call_service(&app, req).await.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[actix_rt::test]
+    async fn negotiated_responses_vary_with_accept() {
+        let user = create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10));
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(InMemoryRepository::with_users(vec![user.clone()])))
+                .configure(crate::v1::service::<InMemoryRepository>),
+        )
+        .await;
+        let get = |uri: &str, accept: &str| {
+            actix_web::test::TestRequest::get().uri(uri).insert_header(("accept", accept.to_string()))
+        };
+
+        let uri = format!("/v1/user/{}", user.id);
+        let res = actix_web::test::call_service(&app, get(&uri, "application/msgpack").to_request()).await;
+        assert_eq!(res.headers().get("vary").unwrap(), "Accept");
+        let etag = res.headers().get("etag").unwrap().clone();
+        let req = get(&uri, "application/msgpack").insert_header(("if-none-match", etag)).to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get("vary").unwrap(), "Accept");
+
+        for (uri, accept) in [("/v1/user", "text/csv"), ("/v1/user/export", "*/*"), (uri.as_str(), "text/html")] {
+            let res = actix_web::test::call_service(&app, get(uri, accept).to_request()).await;
+            assert_eq!(res.headers().get("vary").unwrap(), "Accept", "{} as {}", uri, accept);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn message_pack_ids_are_strings_when_shaped_or_not() {
+        let user = create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10));
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(InMemoryRepository::with_users(vec![user.clone()])))
+                .configure(crate::v1::service::<InMemoryRepository>),
+        )
+        .await;
+        // The `id` key followed by a MessagePack str8 of 36 bytes.
+        let string_id = [0xa2, b'i', b'd', 0xd9, 36];
+
+        let req = actix_web::test::TestRequest::get()
+            .uri(&format!("/v1/user/{}", user.id))
+            .insert_header(("accept", "application/msgpack"))
+            .to_request();
+        let body = actix_web::test::call_and_read_body(&app, req).await;
+        assert!(body.windows(5).any(|window| window == string_id));
+
+        let req = actix_web::test::TestRequest::put()
+            .uri(&format!("/v1/user/{}", user.id))
+            .insert_header(("accept", "application/msgpack"))
+            .set_json(json!({"email": "outro@teste.com", "name": "Outro nome", "birth_date": "1977-03-10"}))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.windows(5).any(|window| window == string_id));
+    }
+
     #[actix_rt::test]
     async fn get_user_with_error() {
         let user_id = uuid::Uuid::parse_str("71802ecd-4eb3-4381-af7e-f737e3a35d5d");
         let mut repo = MockRepository::default();
         repo.expect_get_user()
             .returning(move |_id| Err(Error::new("error".to_string(), 404)));
-        let res = get(web::Path::from(user_id.unwrap()), web::Data::new(repo)).await;
+        let res = get(web::Path::from(user_id.unwrap()), Format::Json, test_request(), web::Data::new(repo)).await;
         assert_eq!(res.status(), StatusCode::NOT_FOUND);
     }
 
+    #[actix_rt::test]
+    async fn get_user_with_database_error() {
+        let user_id = uuid::Uuid::new_v4();
+        let mut repo = MockRepository::default();
+        repo.expect_get_user()
+            .returning(move |_id| Err(Error::new("error".to_string(), 502)));
+        let res = get(web::Path::from(user_id), Format::Json, test_request(), web::Data::new(repo)).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
     #[actix_rt::test]
     async fn create_with_success() {
         let user_id = uuid::Uuid::new_v4();
@@ -141,7 +1106,7 @@ mod tests {
             Ok(new_user)
         });
 
-        let result = post(web::Json(create_user), web::Data::new(repo)).await;
+        let result = post(Encoded(create_user), Format::Json, test_request(), web::Data::new(repo)).await;
 
         assert_eq!(result.status(), StatusCode::CREATED);
     }
@@ -153,7 +1118,33 @@ mod tests {
         let mut repo = MockRepository::default();
         repo.expect_create_user().returning(move |_user| Err(Error::new("error".to_string(), 422)));
 
-        let result = post(web::Json(create_user), web::Data::new(repo)).await;
+        let result = post(Encoded(create_user), Format::Json, test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn create_with_duplicated_email() {
+        let create_user = create_test_user_request(USER_NAME.to_string(), (1977, 3, 10));
+
+        let mut repo = MockRepository::default();
+        repo.expect_create_user().returning(move |_user| {
+            Err(Error::new("error".to_string(), 409).with_field("email".to_string()))
+        });
+
+        let result = post(Encoded(create_user), Format::Json, test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_rt::test]
+    async fn create_with_invalid_user() {
+        let mut repo = MockRepository::default();
+        repo.expect_create_user().never();
+
+        let create_user = CreateUser {
+            email: "not-an-email".to_string(),
+            ..create_test_user_request(USER_NAME.to_string(), (1977, 3, 10))
+        };
+        let result = post(Encoded(create_user), Format::Json, test_request(), web::Data::new(repo)).await;
         assert_eq!(result.status(), StatusCode::UNPROCESSABLE_ENTITY);
     }
 
@@ -165,7 +1156,7 @@ mod tests {
         let mut repo = MockRepository::default();
         repo.expect_update_user().returning(|user| Ok(user.to_owned()));
 
-        let result = put(web::Json(new_user), web::Data::new(repo)).await;
+        let result = put(Encoded(new_user), Format::Json, test_request(), web::Data::new(repo)).await;
         assert_eq!(result.status(), StatusCode::OK);
     }
 
@@ -177,10 +1168,615 @@ mod tests {
         let mut repo = MockRepository::default();
         repo.expect_update_user().returning(|_user| Err(Error::new("error".to_string(), 422)));
 
-        let result = put(web::Json(new_user), web::Data::new(repo)).await;
+        let result = put(Encoded(new_user), Format::Json, test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn update_with_invalid_user() {
+        let mut repo = MockRepository::default();
+        repo.expect_update_user().never();
+
+        let new_user = create_test_user(uuid::Uuid::new_v4(), " ".to_string(), (1977, 3, 10));
+        let result = put(Encoded(new_user), Format::Json, test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(result.headers().get(DEPRECATION_HEADER).unwrap(), "true");
+    }
+
+    #[actix_rt::test]
+    async fn update_on_legacy_route_is_deprecated() {
+        let user_id = uuid::Uuid::new_v4();
+        let new_user = create_test_user(user_id, USER_NAME.to_string(), (1977, 3, 10));
+
+        let mut repo = MockRepository::default();
+        repo.expect_update_user().returning(|user| Ok(user.to_owned()));
+
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(repo))
+                .configure(crate::v1::service::<MockRepository>),
+        )
+        .await;
+        let req = actix_web::test::TestRequest::put().uri("/v1/user").set_json(&new_user).to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("deprecation").unwrap(), "true");
+        assert_eq!(
+            res.headers().get("link").unwrap().to_str().unwrap(),
+            format!("</v1/user/{}>; rel=\"successor-version\"", user_id)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn create_returns_location_and_links() {
+        let user_id = uuid::Uuid::new_v4();
+
+        let mut repo = MockRepository::default();
+        repo.expect_create_user()
+            .returning(move |_user| Ok(create_test_user(user_id, USER_NAME.to_string(), (1977, 3, 10))));
+
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(repo))
+                .configure(crate::v1::service::<MockRepository>),
+        )
+        .await;
+        let body = serde_json::json!({
+            "email": "teste@teste.com",
+            "name": USER_NAME,
+            "birth_date": "1977-03-10",
+            "custom_data": {"random": 1},
+        });
+        let req = actix_web::test::TestRequest::post().uri("/v1/user").set_json(&body).to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+
+        let location = format!("/v1/user/{}", user_id);
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get("location").unwrap().to_str().unwrap(), location);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["_links"]["self"]["href"], location.as_str());
+        assert_eq!(body["_links"]["collection"]["href"], "/v1/user");
+    }
+
+    #[actix_rt::test]
+    async fn create_negotiates_binary_formats() {
+        #[derive(Deserialize)]
+        struct Created {
+            id: Uuid,
+            name: String,
+        }
+
+        let user_id = uuid::Uuid::new_v4();
+        let mut repo = MockRepository::default();
+        repo.expect_create_user()
+            .times(1)
+            .returning(move |user| Ok(create_test_user(user_id, user.name.clone(), (1977, 3, 10))));
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(repo))
+                .configure(crate::v1::service::<MockRepository>),
+        )
+        .await;
+        let body = json!({"email": "teste@teste.com", "name": USER_NAME, "birth_date": "1977-03-10"});
+        let post = |content_type: &str, accept: &str, body: Vec<u8>| {
+            actix_web::test::TestRequest::post()
+                .uri("/v1/user")
+                .insert_header(("content-type", content_type))
+                .insert_header(("accept", accept))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let req = post("application/msgpack", "application/cbor", Format::MessagePack.encode(&body).unwrap());
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get("content-type").unwrap(), "application/cbor");
+        assert!(res.headers().get("location").is_some());
+        let created: Created = Format::Cbor.decode(&actix_web::test::read_body(res).await).unwrap();
+        assert_eq!((created.id, created.name.as_str()), (user_id, USER_NAME));
+
+        let req = post("text/plain", "application/json", body.to_string().into_bytes());
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let req = post("application/json", "text/html", body.to_string().into_bytes());
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+        let err: Error = actix_web::test::read_body_json(res).await;
+        assert!(err.message.starts_with("Acceptable types are application/json"));
+    }
+
+    fn put_user(id: Option<Uuid>) -> Encoded<PutUser> {
+        Encoded(PutUser {
+            id,
+            email: "teste@teste.com".to_string(),
+            name: USER_NAME.to_string(),
+            birth_date: NaiveDate::from_ymd(1977, 3, 10),
+            custom_data: json!({"random": 1}),
+        })
+    }
+
+    #[actix_rt::test]
+    async fn put_by_id_creates_missing_user() {
+        let user_id = uuid::Uuid::new_v4();
+
+        let mut repo = MockRepository::default();
+        repo.expect_upsert_user()
+            .withf(move |user| user.id == user_id)
+            .returning(|user| Ok((user.to_owned(), true)));
+
+        let result = put_by_id(
+            web::Path::from(user_id),
+            put_user(None),
+            Format::Json,
+            test_request(),
+            web::Data::new(repo),
+        )
+        .await;
+        assert_eq!(result.status(), StatusCode::CREATED);
+    }
+
+    #[actix_rt::test]
+    async fn put_by_id_replaces_existing_user() {
+        let user_id = uuid::Uuid::new_v4();
+
+        let mut repo = MockRepository::default();
+        repo.expect_upsert_user().returning(|user| Ok((user.to_owned(), false)));
+
+        let result = put_by_id(
+            web::Path::from(user_id),
+            put_user(Some(user_id)),
+            Format::Json,
+            test_request(),
+            web::Data::new(repo),
+        )
+        .await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn put_by_id_with_mismatched_id() {
+        let mut repo = MockRepository::default();
+        repo.expect_upsert_user().never();
+
+        let body = put_user(Some(uuid::Uuid::new_v4()));
+        let result = put_by_id(
+            web::Path::from(uuid::Uuid::new_v4()),
+            body,
+            Format::Json,
+            test_request(),
+            web::Data::new(repo),
+        )
+        .await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn put_by_id_with_invalid_path() {
+        let app = actix_web::App::new()
+            .app_data(web::Data::new(MockRepository::default()))
+            .configure(crate::v1::service::<MockRepository>);
+        let app = actix_web::test::init_service(app).await;
+        let req = actix_web::test::TestRequest::put()
+            .uri("/v1/user/not-a-uuid")
+            .set_json(serde_json::json!({}))
+            .to_request();
+
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn bulk_with_success() {
+        let mut repo = MockRepository::default();
+        repo.expect_bulk()
+            .withf(|operations, mode| operations.len() == 1 && *mode == BulkMode::BestEffort)
+            .returning(|_operations, _mode| Ok(vec![BulkItemResult::deleted(uuid::Uuid::new_v4())]));
+
+        let request: BulkRequest = serde_json::from_value(serde_json::json!({
+            "mode": "best_effort",
+            "operations": [{"op": "delete", "id": uuid::Uuid::new_v4()}],
+        }))
+        .unwrap();
+        let result = bulk(
+            Encoded(request),
+            Format::Json,
+            web::Data::new(BulkConfig::default()),
+            web::Data::new(repo),
+        )
+        .await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn bulk_with_too_many_operations() {
+        let mut repo = MockRepository::default();
+        repo.expect_bulk().never();
+
+        let request = BulkRequest {
+            mode: BulkMode::Atomic,
+            operations: vec![BulkOperation::Delete { id: uuid::Uuid::new_v4() }; 2],
+        };
+        let config = BulkConfig { max_operations: 1 };
+        let result = bulk(Encoded(request), Format::Json, web::Data::new(config), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_rt::test]
+    async fn bulk_validates_users_before_the_repository() {
+        let operations = serde_json::json!([
+            {"op": "delete", "id": uuid::Uuid::new_v4()},
+            {"op": "update", "user": create_test_user(uuid::Uuid::new_v4(), "".to_string(), (1977, 3, 10))},
+        ]);
+
+        let mut repo = MockRepository::default();
+        repo.expect_bulk().never();
+        let request: BulkRequest = serde_json::from_value(json!({"operations": operations})).unwrap();
+        let config = web::Data::new(BulkConfig::default());
+        let result = bulk(Encoded(request), Format::Json, config.clone(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = actix_web::body::to_bytes(result.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["field"], "operations[1].name");
+
+        let mut repo = MockRepository::default();
+        repo.expect_bulk()
+            .withf(|operations, _mode| operations.len() == 1)
+            .returning(|_operations, _mode| Ok(vec![BulkItemResult::deleted(uuid::Uuid::new_v4())]));
+        let request: BulkRequest =
+            serde_json::from_value(json!({"mode": "best_effort", "operations": operations})).unwrap();
+        let result = bulk(Encoded(request), Format::Json, config, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(result.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["results"][0]["status"], 204);
+        assert_eq!(body["results"][1]["status"], 422);
+        assert_eq!(body["results"][1]["error"]["field"], "name");
+    }
+
+    #[actix_rt::test]
+    async fn bulk_route_is_not_a_user_id() {
+        let mut repo = MockRepository::default();
+        repo.expect_bulk().returning(|_operations, _mode| Ok(vec![]));
+
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(repo))
+                .app_data(web::Data::new(BulkConfig::default()))
+                .configure(crate::v1::service::<MockRepository>),
+        )
+        .await;
+        let req = actix_web::test::TestRequest::post()
+            .uri("/v1/user/_bulk")
+            .set_json(serde_json::json!({"operations": []}))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn get_all_by_ids_reports_missing() {
+        let found = uuid::Uuid::new_v4();
+        let missing = uuid::Uuid::new_v4();
+
+        let mut repo = MockRepository::default();
+        repo.expect_get_users()
+            .withf(move |ids| ids == [missing, found])
+            .returning(move |_ids| Ok(vec![create_test_user(found, USER_NAME.to_string(), (1977, 3, 10))]));
+
+        let query = UserListQuery { ids: Some(format!("{}, {},{}", missing, found, missing)), ..UserListQuery::default() };
+        let result = get_all(web::Query(query), test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+
+        let body = actix_web::body::to_bytes(result.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["users"][0]["id"], found.to_string());
+        assert_eq!(body["missing"], serde_json::json!([missing]));
+    }
+
+    #[actix_rt::test]
+    async fn get_all_by_invalid_ids() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_users().never();
+
+        let query = UserListQuery { ids: Some("not-a-uuid".to_string()), ..UserListQuery::default() };
+        let result = get_all(web::Query(query), test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn batch_with_success() {
+        let ids = vec![uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+
+        let mut repo = MockRepository::default();
+        repo.expect_get_users()
+            .times(1)
+            .returning(|ids| Ok(ids.iter().map(|id| create_test_user(*id, USER_NAME.to_string(), (1977, 3, 10))).collect()));
+
+        let result = batch(Encoded(UserIds { ids }), test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn batch_as_csv_is_not_acceptable() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_users().never();
+
+        let req = actix_web::test::TestRequest::default().insert_header(("accept", "text/csv")).to_http_request();
+        let result = batch(Encoded(UserIds { ids: vec![uuid::Uuid::new_v4()] }), req, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(result.headers().get("content-type").unwrap(), "application/json");
+    }
+
+    #[actix_rt::test]
+    async fn batch_with_too_many_ids() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_users().never();
+
+        let ids = (0..=MAX_IDS).map(|_| uuid::Uuid::new_v4()).collect();
+        let result = batch(Encoded(UserIds { ids }), test_request(), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_rt::test]
+    async fn export_streams_ndjson() {
+        let mut repo = MockRepository::default();
+        repo.expect_export_users().returning(|| {
+            let users = (0..3)
+                .map(|_| Ok(create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))))
+                .collect::<Vec<_>>();
+            futures::stream::iter(users).boxed()
+        });
+
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(repo))
+                .configure(crate::v1::service::<MockRepository>),
+        )
+        .await;
+        let req = actix_web::test::TestRequest::get().uri("/v1/user/export").to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("content-type").unwrap(), NDJSON_CONTENT_TYPE);
+        let body = actix_web::test::read_body(res).await;
+        let users: Vec<User> = body
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(users.len(), 3);
+        assert!(users.iter().all(|user| user.name == USER_NAME));
+    }
+
+    #[actix_rt::test]
+    async fn export_stops_on_database_error() {
+        let mut repo = MockRepository::default();
+        repo.expect_export_users().returning(|| {
+            let users = vec![
+                Ok(create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))),
+                Err(Error::new("error".to_string(), 502)),
+                Ok(create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))),
+            ];
+            futures::stream::iter(users).boxed()
+        });
+
+        let res = export(web::Data::new(repo)).await;
+        let body = actix_web::body::to_bytes(res.into_body()).await;
+        assert!(body.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn get_all_as_csv_streams_rows() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_all().never();
+        repo.expect_export_users().returning(|| {
+            let users = (0..2)
+                .map(|_| Ok(create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))))
+                .collect::<Vec<_>>();
+            futures::stream::iter(users).boxed()
+        });
+
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(repo))
+                .configure(crate::v1::service::<MockRepository>),
+        )
+        .await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/v1/user")
+            .insert_header(("accept", "text/csv"))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("content-type").unwrap(), CSV_CONTENT_TYPE);
+        let body = actix_web::test::read_body(res).await;
+        let lines: Vec<&str> = std::str::from_utf8(&body).unwrap().lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("id,email,name,birth_date,custom_data,"));
+    }
+
+    #[actix_rt::test]
+    async fn import_reports_each_row() {
+        let mut repo = MockRepository::default();
+        repo.expect_bulk()
+            .withf(|operations, mode| operations.len() == 2 && *mode == BulkMode::BestEffort)
+            .returning(|_operations, _mode| {
+                Ok(vec![
+                    BulkItemResult::user(201, create_test_user(uuid::Uuid::new_v4(), USER_NAME.to_string(), (1977, 3, 10))),
+                    BulkItemResult::failed(Error::new("A user with this email already exists".to_string(), 409)),
+                ])
+            });
+
+        let req = actix_web::test::TestRequest::default()
+            .insert_header(("content-type", "text/csv; charset=utf-8"))
+            .to_http_request();
+        let body = web::Bytes::from_static(
+            b"email,name,birth_date,custom_data.random\n\
+              um@teste.com,Um,1977-03-10,1\n\
+              dois@teste.com,,1977-03-10,1\n\
+              um@teste.com,Tres,1977-03-10,1\n",
+        );
+        let result = import(Format::Json, req, body, web::Data::new(BulkConfig::default()), web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+
+        let body = actix_web::body::to_bytes(result.into_body()).await.unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["created"], 1);
+        assert_eq!(report["failed"], 2);
+        let statuses: Vec<(u64, u64)> = report["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| (row["line"].as_u64().unwrap(), row["status"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(statuses, vec![(2, 201), (3, 422), (4, 409)]);
+    }
+
+    #[actix_rt::test]
+    async fn import_with_unsupported_content_type() {
+        let mut repo = MockRepository::default();
+        repo.expect_bulk().never();
+
+        let result = import(
+            Format::Json,
+            test_request(),
+            web::Bytes::from_static(b"email"),
+            web::Data::new(BulkConfig::default()),
+            web::Data::new(repo),
+        )
+        .await;
+        assert_eq!(result.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    fn test_request() -> HttpRequest {
+        actix_web::test::TestRequest::default().to_http_request()
+    }
+
+    fn merge_patch_request() -> HttpRequest {
+        actix_web::test::TestRequest::default()
+            .insert_header(("content-type", MERGE_PATCH_CONTENT_TYPE))
+            .to_http_request()
+    }
+
+    #[actix_rt::test]
+    async fn patch_with_success() {
+        let user_id = uuid::Uuid::new_v4();
+
+        let mut repo = MockRepository::default();
+        repo.expect_get_user()
+            .returning(|id| Ok(create_test_user(*id, USER_NAME.to_string(), (1977, 3, 10))));
+        repo.expect_patch_user()
+            .withf(|_id, changes, _updated_at| {
+                changes == &PatchUser { name: Some("Outro nome".to_string()), ..PatchUser::default() }
+            })
+            .returning(|id, changes, _updated_at| {
+                Ok(create_test_user(*id, changes.name.clone().unwrap(), (1977, 3, 10)))
+            });
+
+        let body = web::Bytes::from_static(br#"{"name": "Outro nome"}"#);
+        let result = patch(
+            web::Path::from(user_id),
+            Format::Json,
+            merge_patch_request(),
+            body,
+            web::Data::new(repo),
+        )
+        .await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn patch_with_invalid_merge_result() {
+        let user_id = uuid::Uuid::new_v4();
+
+        let mut repo = MockRepository::default();
+        repo.expect_get_user()
+            .returning(|id| Ok(create_test_user(*id, USER_NAME.to_string(), (1977, 3, 10))));
+        repo.expect_patch_user().never();
+
+        let body = web::Bytes::from_static(br#"{"email": null}"#);
+        let result = patch(
+            web::Path::from(user_id),
+            Format::Json,
+            merge_patch_request(),
+            body,
+            web::Data::new(repo),
+        )
+        .await;
         assert_eq!(result.status(), StatusCode::UNPROCESSABLE_ENTITY);
     }
 
+    #[actix_rt::test]
+    async fn json_patch_with_success() {
+        let user_id = uuid::Uuid::new_v4();
+
+        let mut repo = MockRepository::default();
+        repo.expect_get_user()
+            .returning(|id| Ok(create_test_user(*id, USER_NAME.to_string(), (1977, 3, 10))));
+        repo.expect_patch_user()
+            .withf(|_id, changes, _updated_at| {
+                changes == &PatchUser { custom_data: Some(json!({"random": 7})), ..PatchUser::default() }
+            })
+            .returning(|id, _changes, _updated_at| Ok(create_test_user(*id, USER_NAME.to_string(), (1977, 3, 10))));
+
+        let req = actix_web::test::TestRequest::default()
+            .insert_header(("content-type", JSON_PATCH_CONTENT_TYPE))
+            .to_http_request();
+        let body = web::Bytes::from_static(br#"[{"op": "replace", "path": "/custom_data/random", "value": 7}]"#);
+        let result = patch(web::Path::from(user_id), Format::Json, req, body, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn json_patch_with_failed_test() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_user()
+            .returning(|id| Ok(create_test_user(*id, USER_NAME.to_string(), (1977, 3, 10))));
+        repo.expect_patch_user().never();
+
+        let req = actix_web::test::TestRequest::default()
+            .insert_header(("content-type", JSON_PATCH_CONTENT_TYPE))
+            .to_http_request();
+        let body = web::Bytes::from_static(br#"[
+            {"op": "replace", "path": "/name", "value": "Outro nome"},
+            {"op": "test", "path": "/email", "value": "outro@teste.com"}
+        ]"#);
+        let result = patch(web::Path::from(uuid::Uuid::new_v4()), Format::Json, req, body, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_rt::test]
+    async fn patch_with_unsupported_content_type() {
+        let repo = MockRepository::default();
+        let req = actix_web::test::TestRequest::default()
+            .insert_header(("content-type", "application/json"))
+            .to_http_request();
+
+        let body = web::Bytes::from_static(br#"{"name": "Outro nome"}"#);
+        let result = patch(web::Path::from(uuid::Uuid::new_v4()), Format::Json, req, body, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[actix_rt::test]
+    async fn patch_with_unknown_user() {
+        let mut repo = MockRepository::default();
+        repo.expect_get_user()
+            .returning(|_id| Err(Error::new("error".to_string(), 404)));
+
+        let body = web::Bytes::from_static(br#"{"name": "Outro nome"}"#);
+        let result = patch(
+            web::Path::from(uuid::Uuid::new_v4()),
+            Format::Json,
+            merge_patch_request(),
+            body,
+            web::Data::new(repo),
+        )
+        .await;
+        assert_eq!(result.status(), StatusCode::NOT_FOUND);
+    }
+
     #[actix_rt::test]
     async fn delete_with_success() {
         let user_id = uuid::Uuid::new_v4();
@@ -188,7 +1784,7 @@ mod tests {
         let mut repo = MockRepository::default();
         repo.expect_delete_user().returning(|id| Ok(id.to_owned()));
 
-        let result = delete(web::Path::from(user_id), web::Data::new(repo)).await;
+        let result = delete(web::Path::from(user_id), Format::Json, web::Data::new(repo)).await;
         assert_eq!(result.status(), StatusCode::NO_CONTENT);
     }
 
@@ -197,9 +1793,18 @@ mod tests {
         let user_id = uuid::Uuid::new_v4();
 
         let mut repo = MockRepository::default();
-        repo.expect_delete_user().returning(|_id| Err(Error::new("error".to_string(), 422)));
+        repo.expect_delete_user().returning(|_id| Err(Error::new("error".to_string(), 502)));
+
+        let result = delete(web::Path::from(user_id), Format::Json, web::Data::new(repo)).await;
+        assert_eq!(result.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_rt::test]
+    async fn delete_missing_user() {
+        let mut repo = MockRepository::default();
+        repo.expect_delete_user().returning(|_id| Err(Error::new("This user does not exist".to_string(), 404)));
 
-        let result = delete(web::Path::from(user_id), web::Data::new(repo)).await;
+        let result = delete(web::Path::from(uuid::Uuid::new_v4()), Format::Json, web::Data::new(repo)).await;
         assert_eq!(result.status(), StatusCode::NOT_FOUND);
     }
 }